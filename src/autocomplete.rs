@@ -0,0 +1,229 @@
+use std::collections::{HashSet, VecDeque};
+
+use parking_lot::Mutex;
+use serenity::all::AutocompleteChoice;
+
+use crate::{Context, history::SourceKind, playlog::PlayFilter};
+
+/// How many previous search results are remembered.
+const SEARCH_CACHE_CAPACITY: usize = 200;
+
+/// Discord doesn't show more autocomplete choices than this.
+const MAX_CHOICES: usize = 25;
+
+/// Discord rejects autocomplete names and values longer than this.
+const MAX_CHOICE_LENGTH: usize = 100;
+
+/// A track which can be suggested to the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    pub kind: SourceKind,
+    pub title: String,
+    pub url: String,
+}
+
+/// Results of previous searches, newest first, so that they can be suggested without searching
+/// again.
+#[derive(Debug, Default)]
+pub struct SearchCache {
+    entries: Mutex<VecDeque<Suggestion>>,
+}
+
+impl SearchCache {
+    /// Remember a search result.
+    pub fn remember(&self, title: String, url: String) {
+        let mut entries = self.entries.lock();
+
+        entries.retain(|entry| entry.url != url);
+        entries.push_front(Suggestion {
+            kind: SourceKind::Youtube,
+            title,
+            url,
+        });
+        entries.truncate(SEARCH_CACHE_CAPACITY);
+    }
+
+    /// Get all remembered search results.
+    pub fn list(&self) -> Vec<Suggestion> {
+        self.entries.lock().iter().cloned().collect()
+    }
+}
+
+/// Score how well `pattern` fuzzy matches `candidate`, higher is better.
+///
+/// All characters of the pattern, except for whitespace, have to appear in the candidate in the
+/// same order, otherwise there is no match. Consecutive characters, characters at the start of
+/// words and whole substring matches are preferred.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let pattern = pattern.trim().to_lowercase();
+    let candidate = candidate.to_lowercase();
+    let chars = candidate.chars().collect::<Vec<_>>();
+
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+
+    for wanted in pattern.chars().filter(|c| !c.is_whitespace()) {
+        let found = next + chars[next..].iter().position(|&c| c == wanted)?;
+
+        score += 1;
+        if found == 0 || !chars[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        match previous {
+            Some(previous) if previous + 1 == found => score += 5,
+            Some(previous) => score -= (found - previous - 1).min(3) as i64,
+            None => {}
+        }
+
+        previous = Some(found);
+        next = found + 1;
+    }
+
+    if !pattern.is_empty() && candidate.contains(&pattern) {
+        score += 20;
+    }
+
+    Some(score)
+}
+
+/// Collect tracks the user likely wants to play, ranked by how well they match `partial`.
+///
+/// The user's favourites come first, then the guild's history and finally previous search
+/// results, which decides the order of equally good matches.
+async fn suggestions(ctx: Context<'_>, partial: &str) -> Vec<Suggestion> {
+    let data = ctx.data();
+    let mut candidates = vec![];
+
+    candidates.extend(
+        data.favourites
+            .list(ctx.author().id)
+            .into_iter()
+            .map(|track| Suggestion {
+                kind: track.kind,
+                title: track.title(),
+                url: track.url(),
+            }),
+    );
+
+    if let Some(guild_id) = ctx.guild_id() {
+        let queue = data.qs.lock().get(&guild_id).cloned();
+        if let Some(queue) = queue {
+            candidates.extend(queue.history().into_iter().rev().map(|track| Suggestion {
+                kind: track.kind,
+                title: track.title(),
+                url: track.url(),
+            }));
+        }
+
+        if let Some(log) = data.play_log.as_ref()
+            && let Ok(plays) = log
+                .run(move |log| log.search(guild_id, &PlayFilter::default(), 200))
+                .await
+        {
+            candidates.extend(plays.into_iter().filter_map(|play| {
+                Some(Suggestion {
+                    kind: SourceKind::from_name(&play.source)?,
+                    title: play.title,
+                    url: play.url,
+                })
+            }));
+        }
+    }
+
+    candidates.extend(data.search_cache.list());
+
+    let mut seen = HashSet::new();
+    let mut ranked = candidates
+        .into_iter()
+        .filter(|candidate| seen.insert(candidate.url.clone()))
+        .filter_map(|candidate| Some((fuzzy_score(partial, &candidate.title)?, candidate)))
+        .collect::<Vec<_>>();
+
+    // The sort is stable, so the order of sources is kept for equal scores.
+    ranked.sort_by_key(|(score, _)| -score);
+
+    ranked
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .take(MAX_CHOICES)
+        .collect()
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(MAX_CHOICE_LENGTH).collect()
+}
+
+/// Autocomplete for queries which are played directly.
+///
+/// The chosen value is the kind of source and the URL of the track, e.g. `stream:https://…`, see
+/// `parse_play_choice`.
+pub async fn play_query(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    suggestions(ctx, partial)
+        .await
+        .into_iter()
+        .map(|suggestion| {
+            let value = format!("{}:{}", suggestion.kind.key(), suggestion.url);
+            (suggestion.title, value)
+        })
+        .filter(|(_, value)| value.len() <= MAX_CHOICE_LENGTH)
+        .map(|(title, value)| AutocompleteChoice::new(truncate(&title), value))
+        .collect()
+}
+
+/// Parse a value chosen from `play_query` into the kind of source and the URL of the track.
+///
+/// Anything typed by hand, including plain URLs, is not a choice.
+pub fn parse_play_choice(value: &str) -> Option<(SourceKind, &str)> {
+    let (key, url) = value.split_once(':')?;
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return None;
+    }
+    Some((SourceKind::from_key(key)?, url))
+}
+
+/// Autocomplete for search queries, the chosen value is the track title.
+pub async fn search_query(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    suggestions(ctx, partial)
+        .await
+        .into_iter()
+        .map(|suggestion| {
+            let title = truncate(&suggestion.title);
+            AutocompleteChoice::new(title.clone(), title)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_ranking() {
+        let exact = fuzzy_score("never gonna", "Rick Astley - Never Gonna Give You Up").unwrap();
+        let scattered = fuzzy_score(
+            "never gonna",
+            "Nine Eleven Very Eerie Rainy Gardens On Northern Avenues",
+        )
+        .unwrap();
+
+        assert!(exact > scattered);
+        assert_eq!(fuzzy_score("xyz", "Never Gonna Give You Up"), None);
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+    }
+
+    #[test]
+    fn play_choices() {
+        assert_eq!(
+            parse_play_choice("stream:https://radio/live"),
+            Some((SourceKind::HttpStream, "https://radio/live"))
+        );
+        assert_eq!(
+            parse_play_choice("file:https://cdn/a.mp3?ex=1"),
+            Some((SourceKind::Attachment, "https://cdn/a.mp3?ex=1"))
+        );
+        assert_eq!(parse_play_choice("https://yt/1"), None);
+        assert_eq!(parse_play_choice("never gonna"), None);
+        assert_eq!(parse_play_choice("file: never gonna"), None);
+    }
+}
@@ -1,26 +1,28 @@
-use crate::{Error, State};
+use crate::{Error, Result_, State, error::ScumboError, utils::reply};
 
-use poise::{BoxFuture, FrameworkError, serenity_prelude::CacheHttp};
+use poise::{
+    BoxFuture, CreateReply, FrameworkContext, FrameworkError,
+    serenity_prelude::{self as serenity, CacheHttp},
+};
 
 /// Code which executes when a command parsing framework error occurs.
+///
+/// Errors which the user can do something about are shown to them as an embed, like the ones the
+/// commands reply with themselves, titled after what went wrong.
 pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
     Box::pin(async move {
         match err {
-            FrameworkError::Setup { error, .. } => println!("setup error: {error}"),
+            FrameworkError::Setup { error, .. } => println!("setup error: {error:?}"),
             FrameworkError::EventHandler { error, .. } => {
-                println!("framework error: {error}")
+                println!("framework error: {error:?}")
             }
             FrameworkError::Command { error, ctx, .. } => {
-                println!("command error: {error}");
-                let _ = ctx
-                    .reply(format!(
-                        "The following error occurred when handling a command: {error}"
-                    ))
-                    .await;
+                log_error(&ctx.command().qualified_name, &error);
+                let _ = ctx.send(error_reply(&error)).await;
             }
             FrameworkError::SubcommandRequired { ctx } => {
                 println!("subcommand required error");
-                let _ = ctx.reply("No subcommand provided.").await;
+                let _ = ctx.send(reply("Error", "No subcommand provided.")).await;
             }
             FrameworkError::CommandPanic { payload, .. } => {
                 println!(
@@ -34,9 +36,11 @@ pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
                 let input = input.unwrap_or("<No input>".into());
                 println!("argument parse error on input: '{}': {error}", &input);
                 let _ = ctx
-                    .reply(format!(
-                        "Failed to parse the command argument: {error}\n\t\ton input: '{}'",
-                        input
+                    .send(reply(
+                        "Error",
+                        format!(
+                            "Failed to parse the command argument: {error}\n\t\ton input: '{input}'"
+                        ),
                     ))
                     .await;
             }
@@ -45,7 +49,10 @@ pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
             } => {
                 println!("command structure mismatch: {description}");
                 let _ = ctx
-                    .reply(format!("Mismatched command structure: {description}"))
+                    .send(reply(
+                        "Error",
+                        format!("Mismatched command structure: {description}"),
+                    ))
                     .await;
             }
             FrameworkError::CooldownHit {
@@ -58,9 +65,12 @@ pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
                     remaining_cooldown.as_secs()
                 );
                 let _ = ctx
-                    .reply(format!(
-                        "Command is on cooldown, {} seconds remaining!",
-                        remaining_cooldown.as_secs()
+                    .send(reply(
+                        "Error",
+                        format!(
+                            "Command is on cooldown, {} seconds remaining!",
+                            remaining_cooldown.as_secs()
+                        ),
                     ))
                     .await;
             }
@@ -71,9 +81,9 @@ pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
             } => {
                 println!("missing bot permissions: {}", missing_permissions);
                 let _ = ctx
-                    .reply(format!(
-                        "Bot is missing the following permissions: {}",
-                        missing_permissions
+                    .send(reply(
+                        "Error",
+                        format!("Bot is missing the following permissions: {missing_permissions}"),
                     ))
                     .await;
             }
@@ -82,38 +92,48 @@ pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
                 ctx,
                 ..
             } => {
-                println!(
-                    "missing user permissions: {}",
-                    missing_permissions.unwrap_or_default()
-                );
+                let missing_permissions = missing_permissions.unwrap_or_default();
+                println!("missing user permissions: {}", missing_permissions);
                 let _ = ctx
-                    .reply(format!(
-                        "User is missing the following permissions: {}",
-                        missing_permissions.unwrap_or_default()
+                    .send(reply(
+                        "Error",
+                        format!("User is missing the following permissions: {missing_permissions}"),
                     ))
                     .await;
             }
             FrameworkError::NotAnOwner { ctx, .. } => {
                 println!("non-owner tried to invoke an owner command");
                 let _ = ctx
-                    .reply("Hey, you can't do that, you are not an owner!")
+                    .send(reply(
+                        "Error",
+                        "Hey, you can't do that, you are not an owner!",
+                    ))
                     .await;
             }
             FrameworkError::GuildOnly { ctx, .. } => {
-                let _ = ctx.reply("Guild only command, sorry!").await;
+                let _ = ctx.send(reply("Error", "Guild only command, sorry!")).await;
             }
             FrameworkError::DmOnly { ctx, .. } => {
-                let _ = ctx.reply("DM only command, sorry!").await;
+                let _ = ctx.send(reply("Error", "DM only command, sorry!")).await;
             }
             FrameworkError::NsfwOnly { ctx, .. } => {
-                let _ = ctx.reply("Not in a *freaky* channel ;)").await;
+                let _ = ctx
+                    .send(reply("Error", "Not in a *freaky* channel ;)"))
+                    .await;
             }
             FrameworkError::CommandCheckFailed { error, ctx, .. } => {
-                println!(
-                    "command check failed with the following error: {}",
-                    error.unwrap_or("<no error>".into())
-                );
-                let _ = ctx.reply("Command check failed.").await;
+                // Checks explain why the command can't be used through their error.
+                let response = match error {
+                    Some(error) => {
+                        log_error(&ctx.command().qualified_name, &error);
+                        error_reply(&error)
+                    }
+                    None => {
+                        println!("command check failed without an error");
+                        reply("Error", "Command check failed.")
+                    }
+                };
+                let _ = ctx.send(response).await;
             }
             FrameworkError::DynamicPrefix { error, .. } => {
                 println!("dynamic prefix function returned an error: {error}");
@@ -130,3 +150,161 @@ pub fn on_error(err: FrameworkError<'_, State, Error>) -> BoxFuture<'_, ()> {
         }
     })
 }
+
+/// Print an error of `command` with everything that caused it.
+fn log_error(command: &str, error: &ScumboError) {
+    println!("command `{command}` failed: {error}");
+
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        println!("  caused by: {cause}");
+        source = cause.source();
+    }
+}
+
+/// Build the reply telling the author what went wrong.
+///
+/// Mistakes of the author are only shown to them, while failures everyone in the channel might
+/// run into are shown to everyone.
+fn error_reply(error: &ScumboError) -> CreateReply {
+    let (title, ephemeral) = match error {
+        ScumboError::NotInGuild
+        | ScumboError::NotInVoice
+        | ScumboError::UserNotInVoice
+        | ScumboError::QueueMissing
+        | ScumboError::NotVoiceChannel
+        | ScumboError::NothingPlaying
+        | ScumboError::TrackChanged
+        | ScumboError::NoPreviousTrack
+        | ScumboError::PlayLogDisabled
+        | ScumboError::InvalidArgument(_) => ("Error", true),
+        ScumboError::Permission(_) | ScumboError::UnknownMember => ("Not allowed", true),
+        ScumboError::QueueFull(_) => ("Queue full", true),
+        ScumboError::Timeout => ("Timed out", false),
+        ScumboError::SourceUnavailable(_) | ScumboError::Decode(_) => ("Track unavailable", false),
+        ScumboError::VoiceUnavailable | ScumboError::Join(_) | ScumboError::Control(_) => {
+            ("Voice error", false)
+        }
+        ScumboError::Http(_) | ScumboError::Io(_) | ScumboError::Database(_) => {
+            ("Something went wrong", false)
+        }
+    };
+
+    reply(title, error.to_string()).ephemeral(ephemeral)
+}
+
+/// Code which executes for every event received from Discord.
+pub fn on_event<'a>(
+    ctx: &'a serenity::Context,
+    event: &'a serenity::FullEvent,
+    framework: FrameworkContext<'a, State, Error>,
+    data: &'a State,
+) -> BoxFuture<'a, Result_<()>> {
+    Box::pin(async move {
+        match event {
+            serenity::FullEvent::VoiceStateUpdate { new, .. } => {
+                if let Some(guild_id) = new.guild_id {
+                    if new.user_id == framework.bot_id && new.channel_id.is_none() {
+                        left_voice(ctx, guild_id, data).await;
+                    }
+                    data.inactivity.voice_state_changed(guild_id);
+                }
+                follow(ctx, new, framework.bot_id, data).await;
+                retract_vote(ctx, new, framework.bot_id, data).await;
+            }
+            serenity::FullEvent::InteractionCreate {
+                interaction: serenity::Interaction::Component(press),
+            } => crate::panel::press(ctx, press, data).await?,
+            _ => {}
+        }
+
+        Ok(())
+    })
+}
+
+/// The bot left its voice channel, by itself or because it was kicked out.
+async fn left_voice(ctx: &serenity::Context, guild_id: serenity::GuildId, data: &State) {
+    // A kicked bot still has its call, which would keep the queue around.
+    if let Some(songbird) = songbird::get(ctx).await
+        && songbird.get(guild_id).is_some()
+    {
+        drop(songbird.remove(guild_id).await);
+    }
+
+    crate::utils::clean_up(data, guild_id).await;
+}
+
+/// The bot moves along with the member it follows, the queue keeps playing.
+async fn follow(
+    ctx: &serenity::Context,
+    state: &serenity::VoiceState,
+    bot_id: serenity::UserId,
+    data: &State,
+) {
+    // Members leaving voice altogether are waited for.
+    let (Some(guild_id), Some(channel_id)) = (state.guild_id, state.channel_id) else {
+        return;
+    };
+    let Some(queue) = data.qs.lock().get(&guild_id).cloned() else {
+        return;
+    };
+    if queue.following() != Some(state.user_id) {
+        return;
+    }
+    let bot_channel = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|guild| guild.voice_states.get(&bot_id)?.channel_id);
+    if bot_channel == Some(channel_id) {
+        return;
+    }
+
+    let Some(songbird) = songbird::get(ctx).await else {
+        return;
+    };
+    if let Err(e) = songbird.join(guild_id, channel_id).await {
+        println!("could not follow {} into {channel_id}: {e}", state.user_id);
+    }
+}
+
+/// Voters who leave the bot's voice channel lose their vote to skip.
+async fn retract_vote(
+    ctx: &serenity::Context,
+    state: &serenity::VoiceState,
+    bot_id: serenity::UserId,
+    data: &State,
+) {
+    let Some(guild_id) = state.guild_id else {
+        return;
+    };
+    let Some(queue) = data.qs.lock().get(&guild_id).cloned() else {
+        return;
+    };
+    let Some(listeners) = ctx
+        .cache
+        .guild(guild_id)
+        .map(|guild| crate::vote::listeners(&guild, bot_id))
+    else {
+        return;
+    };
+    if listeners.contains(&state.user_id) {
+        return;
+    }
+
+    let Some((votes, Some((channel, message)))) = queue.retract_vote(state.user_id) else {
+        return;
+    };
+    let Some(title) = queue.current_queue().first().map(|data| data.title()) else {
+        return;
+    };
+    let percent = data.settings.get(guild_id).vote_skip_percent();
+    let required = crate::vote::required_votes(listeners.len(), percent);
+
+    let _ = channel
+        .edit_message(
+            ctx,
+            message,
+            serenity::EditMessage::new().embed(crate::vote::embed(&title, votes, required)),
+        )
+        .await;
+}
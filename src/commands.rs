@@ -1,16 +1,28 @@
-use crate::{Context, Result_, history::TrackUserData};
+use crate::{
+    Context, Result_,
+    error::ScumboError,
+    filters::Filter,
+    history::SourceKind,
+    playlog::PlayFilter,
+    queue::{LoopMode, Position, Queued, TrackQueue},
+    settings::Setting,
+};
+use poise::{ChoiceParameter, CreateReply};
 use serenity::all::{
-    Attachment, ComponentInteractionCollector, ComponentInteractionDataKind, CreateEmbed,
-    CreateInteractionResponse, CreateMessage, CreateSelectMenu, CreateSelectMenuKind,
+    Attachment, ComponentInteractionCollector, ComponentInteractionDataKind, CreateActionRow,
+    CreateEmbed, CreateInteractionResponse, CreateSelectMenu, CreateSelectMenuKind,
     CreateSelectMenuOption,
 };
-use songbird::input::{HttpRequest, YoutubeDl};
+use songbird::input::YoutubeDl;
 
 use super::utils::reply;
 
 /// Simple `echo` command for parroting everything the user types.
-#[poise::command(prefix_command, category = "Testing")]
-pub async fn echo(ctx: Context<'_>, value: Option<String>) -> Result_<()> {
+#[poise::command(prefix_command, slash_command, category = "Testing")]
+pub async fn echo(
+    ctx: Context<'_>,
+    #[description = "What to say"] value: Option<String>,
+) -> Result_<()> {
     if let Some(v) = value {
         ctx.say(v).await?;
     } else {
@@ -20,8 +32,11 @@ pub async fn echo(ctx: Context<'_>, value: Option<String>) -> Result_<()> {
 }
 
 /// Find out the Discord user id of a mentioned user.
-#[poise::command(prefix_command, owners_only, category = "Testing")]
-pub async fn id(ctx: Context<'_>, user: poise::serenity_prelude::User) -> Result_<()> {
+#[poise::command(prefix_command, slash_command, owners_only, category = "Testing")]
+pub async fn id(
+    ctx: Context<'_>,
+    #[description = "The user to look up"] user: poise::serenity_prelude::User,
+) -> Result_<()> {
     ctx.say(format!("User `{}` id is: `{}`", user.name, user.id))
         .await?;
 
@@ -29,8 +44,11 @@ pub async fn id(ctx: Context<'_>, user: poise::serenity_prelude::User) -> Result
 }
 
 /// Show a help message.
-#[poise::command(prefix_command, track_edits)]
-pub async fn help(ctx: Context<'_>, command: Option<String>) -> Result_<()> {
+#[poise::command(prefix_command, slash_command, track_edits)]
+pub async fn help(
+    ctx: Context<'_>,
+    #[description = "The command to show help for"] command: Option<String>,
+) -> Result_<()> {
     let config = poise::builtins::HelpConfiguration {
         extra_text_at_bottom: "\
 Type '<prefix>help <command>' for more info on a command.
@@ -43,74 +61,190 @@ You can edit your message to the bot and the bot will edit its response.",
     Ok(())
 }
 
+/// Join your voice channel, or the one given.
+///
 /// Join the voice channel that the user is in, alternatively the user can supply a mention of the
 /// voice channel for the bot to join.
-#[poise::command(prefix_command, guild_only, category = "Music")]
+#[poise::command(prefix_command, slash_command, guild_only, category = "Music")]
 pub async fn join(
     ctx: Context<'_>,
+    #[description = "The voice channel to join, instead of yours"]
+    #[channel_types("Voice")]
     voice_channel: Option<serenity::model::channel::GuildChannel>,
 ) -> Result_<()> {
     super::utils::join_voice(ctx, voice_channel).await
 }
 
 /// Leave the voice channel, if inside of one.
-#[poise::command(prefix_command, category = "Music", guild_only)]
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
 pub async fn leave(ctx: Context<'_>) -> Result_<()> {
     let (songbird_manager, has_handler) = super::utils::in_voice(ctx).await?;
 
+    if !has_handler {
+        return Err(ScumboError::NotInVoice);
+    }
+
     // Leave the voice channel and notify the user.
-    if has_handler {
-        songbird_manager.remove(ctx.guild_id().unwrap()).await?;
+    let guild_id = super::utils::guild_id(ctx)?;
+    songbird_manager.remove(guild_id).await?;
+    super::utils::clean_up(ctx.data(), guild_id).await;
 
-        ctx.send(reply("Info", "I have left the voice channel."))
-            .await?;
-    } else {
-        ctx.send(reply("Error", "I am not in a voice channel."))
-            .await?;
+    ctx.send(reply("Info", "I have left the voice channel."))
+        .await?;
+
+    Ok(())
+}
+
+/// Move to another voice channel, the queue keeps playing.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "move",
+    check = "crate::permissions::dj"
+)]
+pub async fn move_to(
+    ctx: Context<'_>,
+    #[description = "The voice channel to move to"]
+    #[channel_types("Voice")]
+    voice_channel: serenity::model::channel::GuildChannel,
+) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let (songbird_manager, has_handler) = super::utils::in_voice(ctx).await?;
+    if !has_handler {
+        return Err(ScumboError::NotInVoice);
+    }
+    if !matches!(voice_channel.kind, serenity::all::ChannelType::Voice) {
+        return Err(ScumboError::NotVoiceChannel);
     }
+
+    // Joining with the existing call keeps its driver, and with it every track.
+    songbird_manager.join(guild_id, voice_channel.id).await?;
+    ctx.send(reply("Info", format!("Moved to {voice_channel}.")))
+        .await?;
+
     Ok(())
 }
 
-/// Resume playing a song or try to play the first result of the query search.
+/// Follow you into every voice channel you switch to, or stop following you.
 #[poise::command(
     prefix_command,
+    slash_command,
     category = "Music",
     guild_only,
-    subcommands("search", "url", "file")
+    check = "crate::permissions::dj"
 )]
-pub async fn play(ctx: Context<'_>, query: Option<String>) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in server.");
+pub async fn follow(ctx: Context<'_>) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
     let (songbird_manager, has_handler) = super::utils::in_voice(ctx).await?;
     if !has_handler {
-        super::utils::join_voice(ctx, None).await?;
+        return Err(ScumboError::NotInVoice);
     }
+    let q = super::utils::guild_queue(ctx)?;
 
-    let call = songbird_manager
-        .get(ctx.guild_id().expect("Only in guilds"))
-        .expect("Should be connected.");
+    let author = ctx.author().id;
+    if q.following() == Some(author) {
+        q.set_following(None);
+        ctx.send(reply("Info", "I stopped following you.")).await?;
+        return Ok(());
+    }
+    q.set_following(Some(author));
+
+    // Catch up with the author right away, if they are somewhere else already.
+    let (author_channel, bot_channel) = {
+        let guild = ctx.guild().ok_or(ScumboError::NotInGuild)?;
+        let channel = |user| guild.voice_states.get(&user)?.channel_id;
+        (channel(author), channel(ctx.framework().bot_id))
+    };
+    if let Some(channel) = author_channel.filter(|&channel| Some(channel) != bot_channel) {
+        songbird_manager.join(guild_id, channel).await?;
+    }
+
+    ctx.send(reply(
+        "Info",
+        "I follow you into every voice channel you switch to now, use `follow` again to stop.",
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Resume playing a song or try to play the first result of the query search.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    subcommands("play_query", "search", "url", "file")
+)]
+pub async fn play(ctx: Context<'_>, #[rest] query: Option<String>) -> Result_<()> {
+    play_or_resume(ctx, query).await
+}
+
+/// Resume playing a song or try to play the first result of the query search.
+///
+/// This is the same as `play`, because slash commands with subcommands can't take arguments.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "query"
+)]
+pub async fn play_query(
+    ctx: Context<'_>,
+    #[rest]
+    #[description = "What to search for, leave empty to resume"]
+    #[autocomplete = "crate::autocomplete::play_query"]
+    query: Option<String>,
+) -> Result_<()> {
+    play_or_resume(ctx, query).await
+}
+
+async fn play_or_resume(ctx: Context<'_>, query: Option<String>) -> Result_<()> {
+    // Resolving the query can take a while.
+    ctx.defer().await?;
+
+    let q = super::utils::join_queue(ctx).await?;
 
     match query {
         Some(query_) => {
-            let mut driver = call.lock().await;
-            let user_data = ctx.data();
-            let client = user_data.client.clone();
-            let q = user_data
-                .qs
-                .lock()
-                .get(&guild_id)
-                .expect("Should have been created when joining.")
-                .clone();
-            let search = YoutubeDl::new_search(client, query_);
-
-            let _ = q.add_from_youtube(search.into(), &mut driver).await?;
+            let client = ctx.data().client.clone();
+            let author = ctx.author().id;
+            // Autocompleted queries are tracks played before, which are added the same way again.
+            let data = match crate::autocomplete::parse_play_choice(&query_) {
+                Some((SourceKind::Attachment, url)) => {
+                    q.add_from_file_url(url.into(), author).await?
+                }
+                Some((SourceKind::HttpStream, url)) => {
+                    q.add_from_stream(url.into(), author).await?
+                }
+                Some((SourceKind::Youtube, url)) => {
+                    let input = YoutubeDl::new(client, url.to_string()).into();
+                    q.add_from_youtube(input, author, Position::Back).await?
+                }
+                None => {
+                    // URLs don't need to be searched for.
+                    let search = if query_.starts_with("https://") || query_.starts_with("http://")
+                    {
+                        YoutubeDl::new(client, query_)
+                    } else {
+                        YoutubeDl::new_search(client, query_)
+                    };
+                    q.add_from_youtube(search.into(), author, Position::Back)
+                        .await?
+                }
+            };
+            ctx.send(reply(
+                "Info",
+                format!("Added {} to the queue.", data.title()),
+            ))
+            .await?;
         }
         None => {
-            ctx.data()
-                .qs
-                .lock()
-                .get(&guild_id)
-                .expect("Should have been created when joining.")
-                .resume()?;
+            q.resume()?;
+            ctx.send(reply("Info", "Resumed.")).await?;
         }
     }
 
@@ -118,17 +252,16 @@ pub async fn play(ctx: Context<'_>, query: Option<String>) -> Result_<()> {
 }
 
 /// Search for `query` on `YouTube` and return a list of search results.
-#[poise::command(prefix_command, category = "Music", guild_only)]
-pub async fn search(ctx: Context<'_>, query: String) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in a server.");
-    let (songbird_manager, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        super::utils::join_voice(ctx, None).await?;
-    }
-
-    let call = songbird_manager
-        .get(guild_id)
-        .expect("Should be in a voice channel.");
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn search(
+    ctx: Context<'_>,
+    #[description = "What to search for"]
+    #[autocomplete = "crate::autocomplete::search_query"]
+    query: String,
+) -> Result_<()> {
+    // Joining and searching can take longer than Discord waits for a response.
+    ctx.defer().await?;
+    let q = super::utils::join_queue(ctx).await?;
 
     let user_data = ctx.data();
     let client = user_data.client.clone();
@@ -136,12 +269,17 @@ pub async fn search(ctx: Context<'_>, query: String) -> Result_<()> {
     // Run the search.
     let mut youtube_search = YoutubeDl::new_search(client.clone(), query);
     let search_results = youtube_search.search(Some(10)).await?.collect::<Vec<_>>();
+    for meta in &search_results {
+        if let (Some(title), Some(url)) = (&meta.title, &meta.source_url) {
+            user_data.search_cache.remember(title.clone(), url.clone());
+        }
+    }
 
     // Create a message for the user to pick the result.
     // TODO: make this nice and make it used thumbnails.
-    let selection_context = CreateMessage::new()
+    let selection_context = CreateReply::default()
         .embed(CreateEmbed::new().title("Serach results:"))
-        .select_menu(CreateSelectMenu::new(
+        .components(vec![CreateActionRow::SelectMenu(CreateSelectMenu::new(
             "search-select-menu",
             CreateSelectMenuKind::String {
                 options: search_results
@@ -153,11 +291,8 @@ pub async fn search(ctx: Context<'_>, query: String) -> Result_<()> {
                     })
                     .collect(),
             },
-        ));
-    let select_message = ctx
-        .channel_id()
-        .send_message(ctx.http(), selection_context)
-        .await?;
+        ))]);
+    let select_message = ctx.send(selection_context).await?;
 
     let mut url = None;
 
@@ -181,84 +316,61 @@ pub async fn search(ctx: Context<'_>, query: String) -> Result_<()> {
 
         // After getting the correct selection, remove the message.
         if url.is_some() {
-            select_message.delete(ctx.http()).await?;
+            select_message.delete(ctx).await?;
             break;
         }
     }
 
-    let url = url.unwrap();
+    // Nothing was picked in time.
+    let url = url.ok_or(ScumboError::Timeout)?;
 
     let source = YoutubeDl::new(client, url);
-
-    {
-        let q = user_data
-            .qs
-            .lock()
-            .get(&guild_id)
-            .expect("Should have been initialized.")
-            .clone();
-        let mut driver = call.lock().await;
-
-        q.add_from_youtube(source.into(), &mut driver).await?;
-    }
+    let data = q
+        .add_from_youtube(source.into(), ctx.author().id, Position::Back)
+        .await?;
+    ctx.send(reply(
+        "Info",
+        format!("Added {} to the queue.", data.title()),
+    ))
+    .await?;
 
     Ok(())
 }
 
 /// Try to play a song from the provided URL.
-#[poise::command(prefix_command, category = "Music", guild_only)]
-pub async fn url(ctx: Context<'_>, url: String) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in server.");
-    let (songbird_manager, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        super::utils::join_voice(ctx, None).await?;
-    }
-
-    let call = songbird_manager
-        .get(ctx.guild_id().expect("Only in guilds"))
-        .expect("Should be connected.");
-
-    let mut driver = call.lock().await;
-    let user_data = ctx.data();
-    let client = user_data.client.clone();
-    let q = user_data
-        .qs
-        .lock()
-        .get(&guild_id)
-        .expect("Should have been created when joining.")
-        .clone();
-    let search = HttpRequest::new(client, url.clone());
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn url(
+    ctx: Context<'_>,
+    #[description = "URL of the audio stream"] url: String,
+) -> Result_<()> {
+    ctx.defer().await?;
+    let q = super::utils::join_queue(ctx).await?;
 
-    let _ = q.add_from_stream(search.into(), url, &mut driver).await?;
+    let data = q.add_from_stream(url, ctx.author().id).await?;
+    ctx.send(reply(
+        "Info",
+        format!("Added {} to the queue.", data.title()),
+    ))
+    .await?;
 
     Ok(())
 }
 
 /// Play a file attached to the message.
-#[poise::command(prefix_command, category = "Music", guild_only)]
-pub async fn file(ctx: Context<'_>, file: Attachment) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in a server.");
-    let (songbird_manager, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        super::utils::join_voice(ctx, None).await?;
-    }
-
-    let call = songbird_manager
-        .get(guild_id)
-        .expect("Should be connected to voice.");
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn file(
+    ctx: Context<'_>,
+    #[description = "The audio file to play"] file: Attachment,
+) -> Result_<()> {
+    ctx.defer().await?;
+    let q = super::utils::join_queue(ctx).await?;
 
-    {
-        let mut driver = call.lock().await;
-        let q = ctx
-            .data()
-            .qs
-            .lock()
-            .get(&guild_id)
-            .expect("Should have been created.")
-            .clone();
-
-        let _ = q.add_from_attachment(file, &mut driver).await?;
-    }
+    let data = q.add_from_attachment(file, ctx.author().id).await?;
+    ctx.send(reply(
+        "Info",
+        format!("Added {} to the queue.", data.title()),
+    ))
+    .await?;
 
     Ok(())
 }
@@ -266,8 +378,18 @@ pub async fn file(ctx: Context<'_>, file: Attachment) -> Result_<()> {
 /// Subcommands for manipulating the queue.
 #[poise::command(
     prefix_command,
+    slash_command,
     category = "Music",
-    subcommands("show", "history", "shuffle"),
+    subcommands(
+        "show",
+        "history",
+        "shuffle",
+        "remove",
+        "move_track",
+        "swap",
+        "next",
+        "dedupe"
+    ),
     subcommand_required,
     guild_only
 )]
@@ -276,23 +398,13 @@ pub async fn queue(_: Context<'_>) -> Result_<()> {
 }
 
 /// Show the contents of the queue.
-#[poise::command(prefix_command, category = "Music", guild_only)]
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
 pub async fn show(ctx: Context<'_>) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in a server.");
-    let (_, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        ctx.send(reply("Error", "Not in a voice channel, good sir!"))
-            .await?;
+    let queued = super::utils::voice_queue(ctx).await?.current_queue();
+    if queued.is_empty() {
+        ctx.send(reply("Info", "The queue is empty.")).await?;
         return Ok(());
     }
-    let queued = ctx
-        .data()
-        .qs
-        .lock()
-        .get(&guild_id)
-        .expect("Should have been initialized")
-        .clone()
-        .current_queue();
 
     let pages = queued
         .chunks(10)
@@ -300,24 +412,7 @@ pub async fn show(ctx: Context<'_>) -> Result_<()> {
         .map(|(chunk, handles)| {
             let mut page = String::new();
             for (i, handle) in handles.iter().enumerate() {
-                match handle.data::<TrackUserData>().as_ref() {
-                    TrackUserData::Youtube { title, url: _ } => page.push_str(&format!(
-                        "{}. {} (from YouTube)\n",
-                        chunk * 10 + i + 1,
-                        title
-                    )),
-                    TrackUserData::Attachment {
-                        title,
-                        attachment_url: _,
-                    } => page.push_str(&format!(
-                        "{}. {} (from file attachment)\n",
-                        chunk * 10 + i + 1,
-                        title
-                    )),
-                    TrackUserData::HttpStream { url } => {
-                        page.push_str(&format!("{}. {} (http stream)\n", chunk * 10 + i + 1, url))
-                    }
-                }
+                page.push_str(&format!("{}. {}\n", chunk * 10 + i + 1, handle.describe()));
             }
             page
         })
@@ -329,120 +424,1044 @@ pub async fn show(ctx: Context<'_>) -> Result_<()> {
 }
 
 /// Show the song history.
-#[poise::command(prefix_command, category = "Music", guild_only)]
-pub async fn history(ctx: Context<'_>) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in a server.");
-    let (_, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        ctx.send(reply("Error", "Not in a voice channel, good sir!"))
-            .await?;
+///
+/// The history can be filtered with `[--user <user>] [--since <age>] [title]`, e.g.
+/// `--since 7d never gonna`. Filtering by user and age needs the play log to be enabled, tracks
+/// found in it have no index to `replay` them with.
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[rest]
+    #[description = "[--user <user>] [--since <age>] [title]"]
+    filters: Option<String>,
+) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let filter = PlayFilter::parse(filters.as_deref().unwrap_or_default())
+        .map_err(ScumboError::InvalidArgument)?;
+
+    let lines = match ctx.data().play_log.as_ref() {
+        // The play log remembers more than the in-memory history, so use it when searching.
+        Some(log) if !filter.is_empty() => log
+            .run({
+                let filter = filter.clone();
+                move |log| log.search(guild_id, &filter, 100)
+            })
+            .await?
+            .into_iter()
+            // Not numbered, `replay` only knows the history shown without filters.
+            .map(|play| format!("- {}\n", play.describe()))
+            .collect::<Vec<_>>(),
+        _ => {
+            if filter.user.is_some() || filter.since.is_some() {
+                return Err(ScumboError::PlayLogDisabled);
+            }
+
+            let queued = super::utils::voice_queue(ctx).await?.history();
+
+            let needle = filter.title.map(|title| title.to_lowercase());
+            queued
+                .iter()
+                .enumerate()
+                .filter(|(_, data)| {
+                    needle
+                        .as_ref()
+                        .is_none_or(|needle| data.title().to_lowercase().contains(needle))
+                })
+                .map(|(i, data)| format!("{}. {}\n", i + 1, data.describe()))
+                .collect()
+        }
+    };
+
+    if lines.is_empty() {
+        ctx.send(reply("Info", "No tracks found.")).await?;
         return Ok(());
     }
-    let queued = ctx
-        .data()
-        .qs
-        .lock()
-        .get(&guild_id)
-        .expect("Should have been initialized")
-        .clone()
-        .history();
 
-    let pages = queued
-        .chunks(10)
+    let pages = lines.chunks(10).map(|page| page.concat()).collect();
+    super::utils::paginate(ctx, pages).await?;
+
+    Ok(())
+}
+
+/// Show statistics about the tracks played in this server.
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn stats(ctx: Context<'_>) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let Some(log) = ctx.data().play_log.as_ref() else {
+        return Err(ScumboError::PlayLogDisabled);
+    };
+
+    let stats = log.run(move |log| log.stats(guild_id, 5)).await?;
+    let top_tracks = stats
+        .top_tracks
+        .iter()
         .enumerate()
-        .map(|(chunk, handles)| {
-            let mut page = String::new();
-            for (i, handle) in handles.iter().enumerate() {
-                match handle {
-                    TrackUserData::Youtube { title, url: _ } => page.push_str(&format!(
-                        "{}. {} (from YouTube)\n",
-                        chunk * 10 + i + 1,
-                        title
-                    )),
-                    TrackUserData::Attachment {
-                        title,
-                        attachment_url: _,
-                    } => page.push_str(&format!(
-                        "{}. {} (from file attachment)\n",
-                        chunk * 10 + i + 1,
-                        title
-                    )),
-                    TrackUserData::HttpStream { url } => {
-                        page.push_str(&format!("{}. {} (http stream)\n", chunk * 10 + i + 1, url))
-                    }
-                }
-            }
-            page
-        })
-        .collect::<Vec<_>>();
+        .map(|(i, (title, plays))| format!("{}. {title} ({plays}x)\n", i + 1))
+        .collect::<String>();
+    let top_requesters = stats
+        .top_requesters
+        .iter()
+        .enumerate()
+        .map(|(i, (user, plays))| format!("{}. <@{user}> ({plays} tracks)\n", i + 1))
+        .collect::<String>();
+    let listened = stats.total_listened.as_secs();
 
-    super::utils::paginate(ctx, pages).await?;
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title("Statistics")
+                .description(format!(
+                    "{} tracks played, {}h {}m listened in total.",
+                    stats.plays,
+                    listened / 3600,
+                    listened / 60 % 60
+                ))
+                .field("Most played", or_nothing(top_tracks), false)
+                .field("Top requesters", or_nothing(top_requesters), false),
+        ),
+    )
+    .await?;
 
     Ok(())
 }
 
+fn or_nothing(text: String) -> String {
+    if text.is_empty() {
+        "Nothing yet.".into()
+    } else {
+        text
+    }
+}
+
 /// Shuffle the queue.
-#[poise::command(prefix_command, category = "Music", guild_only)]
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    check = "crate::permissions::dj"
+)]
 pub async fn shuffle(ctx: Context<'_>) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in a server.");
-    let (_, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        ctx.send(reply("Error", "Not in a voice channel, good sir!"))
-            .await?;
-        return Ok(());
+    let q = super::utils::voice_queue(ctx).await?;
+
+    q.shuffle();
+    ctx.send(reply("Info", "Shuffled the queue.")).await?;
+
+    Ok(())
+}
+
+/// Remove a track, or a range of tracks (e.g. `2-5`), from the queue.
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Index or range of indices, e.g. 2-5"] range: String,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let Some(range) = super::utils::parse_range(&range) else {
+        return Err(ScumboError::InvalidArgument(format!(
+            "`{range}` is not an index or a range of indices."
+        )));
+    };
+
+    if *range.start() < 2 {
+        return Err(ScumboError::InvalidArgument(
+            "The currently playing track cannot be removed, use `skip` instead.".into(),
+        ));
     }
 
-    ctx.data()
-        .qs
-        .lock()
-        .get(&guild_id)
-        .expect("Should be initialized.")
-        .clone()
-        .shuffle();
+    // The tracks are checked when they are removed, the queue might change until then.
+    let dj = crate::permissions::is_dj(ctx).await?;
+    let author = ctx.author().id;
+    let Some(removed) = q.remove_if(range.start() - 1..=range.end() - 1, |tracks| {
+        dj || tracks.iter().all(|track| track.requester() == Some(author))
+    }) else {
+        return Err(ScumboError::Permission(
+            "You can only remove tracks you requested yourself, unless you are a DJ.".into(),
+        ));
+    };
+
+    match removed.as_slice() {
+        [] => {
+            return Err(ScumboError::InvalidArgument(
+                "There are no tracks to remove at those indices.".into(),
+            ));
+        }
+        [track] => {
+            ctx.send(reply("Info", format!("Removed {}.", track.title())))
+                .await?
+        }
+        tracks => {
+            ctx.send(reply("Info", format!("Removed {} tracks.", tracks.len())))
+                .await?
+        }
+    };
+
+    Ok(())
+}
+
+/// Move a track in the queue from one index to another.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "move",
+    check = "crate::permissions::dj"
+)]
+pub async fn move_track(
+    ctx: Context<'_>,
+    #[description = "Index of the track to move"] from: usize,
+    #[description = "Index to move the track to"] to: usize,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let moved = from > 1 && to > 1 && q.move_track(from - 1, to - 1);
+
+    if !moved {
+        return Err(ScumboError::InvalidArgument(
+            "Cannot move that track, the indices must be between 2 and the queue length.".into(),
+        ));
+    }
+    ctx.send(reply("Info", format!("Moved track {from} to {to}.")))
+        .await?;
+
+    Ok(())
+}
+
+/// Swap two tracks in the queue.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    check = "crate::permissions::dj"
+)]
+pub async fn swap(
+    ctx: Context<'_>,
+    #[description = "Index of the first track"] a: usize,
+    #[description = "Index of the second track"] b: usize,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let swapped = a > 1 && b > 1 && q.swap(a - 1, b - 1);
+
+    if !swapped {
+        return Err(ScumboError::InvalidArgument(
+            "Cannot swap those tracks, the indices must be between 2 and the queue length.".into(),
+        ));
+    }
+    ctx.send(reply("Info", format!("Swapped tracks {a} and {b}.")))
+        .await?;
+
+    Ok(())
+}
+
+/// Search for `query` on `YouTube` and play the first result right after the current track.
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn next(
+    ctx: Context<'_>,
+    #[rest]
+    #[description = "What to search for"]
+    query: String,
+) -> Result_<()> {
+    ctx.defer().await?;
+    let q = super::utils::join_queue(ctx).await?;
+
+    let user_data = ctx.data();
+    let client = user_data.client.clone();
+    let search = YoutubeDl::new_search(client, query);
+
+    let data = q
+        .add_from_youtube(search.into(), ctx.author().id, Position::Next)
+        .await?;
+    ctx.send(reply("Info", format!("{} will play next.", data.title())))
+        .await?;
+
+    Ok(())
+}
+
+/// Remove tracks which are already somewhere earlier in the queue.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    check = "crate::permissions::dj"
+)]
+pub async fn dedupe(ctx: Context<'_>) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let removed = q.dedupe();
+
+    ctx.send(reply(
+        "Info",
+        format!("Removed {removed} duplicate track(s)."),
+    ))
+    .await?;
 
     Ok(())
 }
 
 /// Pause the currently playing track.
-#[poise::command(prefix_command, category = "Music", guild_only)]
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
 pub async fn pause(ctx: Context<'_>) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Is in a guild.");
-    let (_, has_handler) = super::utils::in_voice(ctx).await?;
-    if !has_handler {
-        ctx.send(reply("Error", "Not in a voice channel, good sir!"))
-            .await?;
-        return Ok(());
-    }
+    let q = super::utils::voice_queue(ctx).await?;
 
-    ctx.data()
-        .qs
-        .lock()
-        .get(&guild_id)
-        .expect("Should have been created.")
-        .clone()
-        .pause()?;
+    q.pause()?;
+    ctx.send(reply("Info", "Paused.")).await?;
 
     Ok(())
 }
 
-/// Stop all queued tracks.
-#[poise::command(prefix_command, category = "Music", guild_only)]
-pub async fn stop(ctx: Context<'_>) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("Should be in server.");
-    let (_, has_handler) = super::utils::in_voice(ctx).await?;
+/// Show the currently playing track and how far it has played.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    aliases("np")
+)]
+pub async fn nowplaying(ctx: Context<'_>) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
 
-    if !has_handler {
-        ctx.send(reply("Error", "Not in a voice channel, good sir!"))
-            .await?;
-        return Ok(());
+    match crate::panel::now_playing(&q).await {
+        Some(embed) => ctx.send(CreateReply::default().embed(embed).reply(true)),
+        None => ctx.send(reply("Info", "Nothing is playing right now.")),
     }
+    .await?;
 
-    ctx.data()
-        .qs
-        .lock()
-        .get(&guild_id)
-        .expect("Should have been created.")
-        .stop();
+    Ok(())
+}
+
+/// Jump to a position in the current track, e.g. `1:23`, or relative to it, e.g. `+30s` or `-10s`.
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn seek(
+    ctx: Context<'_>,
+    #[description = "e.g. 1:23, +30s or -10s"] timestamp: String,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let Some(seek) = super::utils::parse_seek(&timestamp) else {
+        return Err(ScumboError::InvalidArgument(format!(
+            "`{timestamp}` is not a timestamp, use e.g. `1:23`, `+30s` or `-10s`."
+        )));
+    };
+
+    let (Some(handle), Some(data)) = (q.current(), q.current_queue().first().cloned()) else {
+        return Err(ScumboError::NothingPlaying);
+    };
+    if !crate::permissions::may_change(ctx, std::slice::from_ref(&data)).await? {
+        return Err(ScumboError::Permission(
+            "You can only seek in tracks you requested yourself, unless you are a DJ.".into(),
+        ));
+    }
+    // Live streams have no known length and can't be rewound or skipped ahead.
+    if data.kind == SourceKind::HttpStream && data.duration.is_none() {
+        return Err(ScumboError::InvalidArgument(format!(
+            "Can't seek in {}, it is a live stream.",
+            data.title
+        )));
+    }
+
+    let Some(target) = seek.target(handle.get_info().await?.position) else {
+        return Err(ScumboError::InvalidArgument(format!(
+            "`{timestamp}` is too far ahead."
+        )));
+    };
+    // Positions are in the time the listeners hear, which the speed filters change.
+    if let Some(duration) = q.current_duration()
+        && target >= duration
+    {
+        return Err(ScumboError::InvalidArgument(format!(
+            "`{timestamp}` is past the end of the track, which is {} long.",
+            super::utils::format_duration(duration)
+        )));
+    }
+
+    let position = handle.seek_async(target).await?;
+    ctx.send(reply(
+        "Info",
+        format!("Jumped to {}.", super::utils::format_duration(position)),
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Show or change the volume of the server, in percent.
+///
+/// Use `volume track` to change the volume of just the current track.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    subcommands("volume_server", "volume_track")
+)]
+pub async fn volume(
+    ctx: Context<'_>,
+    #[description = "The new volume, from 0 to 200%"] percent: Option<u16>,
+) -> Result_<()> {
+    server_volume(ctx, percent).await
+}
+
+/// Show or change the volume of the server, in percent.
+///
+/// This is the same as `volume`, because slash commands with subcommands can't take arguments.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "server"
+)]
+pub async fn volume_server(
+    ctx: Context<'_>,
+    #[description = "The new volume, from 0 to 200%"] percent: Option<u16>,
+) -> Result_<()> {
+    server_volume(ctx, percent).await
+}
+
+async fn server_volume(ctx: Context<'_>, percent: Option<u16>) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let data = ctx.data();
+
+    let Some(percent) = percent else {
+        let queue = data.qs.lock().get(&guild_id).cloned();
+        let mut description = format!(
+            "The volume is {}.",
+            data.settings
+                .get(guild_id)
+                .get(Setting::Volume, &data.config)
+        );
+        if let Some(volume) = queue.and_then(|queue| queue.track_volume()) {
+            description.push_str(&format!(
+                " The current track plays at {}%.",
+                crate::panel::percent(volume)
+            ));
+        }
+        ctx.send(reply("Info", description)).await?;
+        return Ok(());
+    };
+
+    if !crate::permissions::is_dj(ctx).await? {
+        return Err(ScumboError::Permission(
+            "Only DJs can change the volume of the whole server, use `volume track` to change \
+             the volume of your own track."
+                .into(),
+        ));
+    }
+
+    // The volume is a setting of the guild, so that it is kept for new queues.
+    let settings = data.settings.update(guild_id, |settings| {
+        settings.set(Setting::Volume, &percent.to_string())
+    })?;
+
+    let queue = data.qs.lock().get(&guild_id).cloned();
+    if let Some(queue) = queue {
+        queue.apply_settings(&settings, &data.config);
+    }
+    ctx.send(reply("Info", format!("Volume set to {percent}%.")))
+        .await?;
+
+    Ok(())
+}
+
+/// Change the volume of just the current track, in percent, or reset it without a volume.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "track"
+)]
+pub async fn volume_track(
+    ctx: Context<'_>,
+    #[description = "The volume of the track, from 0 to 200%, leave out to reset it"]
+    percent: Option<u16>,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    if percent.is_some_and(|percent| percent > crate::settings::MAX_VOLUME) {
+        return Err(ScumboError::InvalidArgument(format!(
+            "The volume has to be between 0 and {}%.",
+            crate::settings::MAX_VOLUME
+        )));
+    }
+
+    let queued = q.current_queue();
+    if !crate::permissions::may_change(ctx, &queued[..queued.len().min(1)]).await? {
+        return Err(ScumboError::Permission(
+            "You can only change the volume of tracks you requested yourself, unless you are a \
+             DJ."
+            .into(),
+        ));
+    }
+
+    let volume = percent.map(|percent| f32::from(percent) / 100.0);
+    let description = match (q.set_track_volume(volume), percent) {
+        (false, _) => return Err(ScumboError::NothingPlaying),
+        (true, Some(percent)) => format!("The current track now plays at {percent}%."),
+        (true, None) => format!(
+            "The current track plays at the server's volume of {}% again.",
+            crate::panel::percent(q.volume())
+        ),
+    };
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Change how the music sounds, e.g. with an equalizer or nightcore.
+///
+/// Filters apply to the playing track immediately, and to every track after it.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    subcommands("filter_list", "filter_enable", "filter_clear")
+)]
+pub async fn filter(ctx: Context<'_>) -> Result_<()> {
+    list_filters(ctx).await
+}
+
+/// Show the enabled filters, and every filter there is.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "list"
+)]
+pub async fn filter_list(ctx: Context<'_>) -> Result_<()> {
+    list_filters(ctx).await
+}
+
+async fn list_filters(ctx: Context<'_>) -> Result_<()> {
+    let filters = super::utils::voice_queue(ctx).await?.filters();
+
+    let enabled = filters.list();
+    let mut description = if enabled.is_empty() {
+        "No filters are enabled.".to_string()
+    } else {
+        let names: Vec<_> = enabled
+            .iter()
+            .map(|filter| format!("`{}`", filter.name()))
+            .collect();
+        format!("Enabled filters: {}.", names.join(", "))
+    };
+
+    description.push_str("\n\n**Filters**");
+    for filter in Filter::ALL {
+        description.push_str(&format!("\n`{}`: {}", filter.name(), filter.description()));
+    }
+    description.push_str("\n\nOnly one equalizer preset and one speed can be enabled at a time.");
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Enable a filter, replacing a filter of the same kind.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "enable",
+    check = "crate::permissions::dj"
+)]
+pub async fn filter_enable(
+    ctx: Context<'_>,
+    #[description = "The filter to enable"] filter: Filter,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+    let filters = q.filters();
+
+    let description = match filters.enable(filter) {
+        Some(replaced) if replaced == filter => format!("`{}` is already enabled.", filter.name()),
+        Some(replaced) => format!(
+            "Enabled `{}` instead of `{}`.",
+            filter.name(),
+            replaced.name()
+        ),
+        None => format!("Enabled `{}`.", filter.name()),
+    };
+    q.refilter().await;
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Disable a filter, or all filters.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "clear",
+    check = "crate::permissions::dj"
+)]
+pub async fn filter_clear(
+    ctx: Context<'_>,
+    #[description = "The filter to disable, leave out to disable all"] filter: Option<Filter>,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+    let filters = q.filters();
+
+    let description = match filter {
+        Some(filter) if filters.disable(filter) => format!("Disabled `{}`.", filter.name()),
+        Some(filter) => format!("`{}` is not enabled.", filter.name()),
+        None => {
+            filters.clear();
+            "Disabled all filters.".to_string()
+        }
+    };
+    q.refilter().await;
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Stop all queued tracks.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    check = "crate::permissions::dj"
+)]
+pub async fn stop(ctx: Context<'_>) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    q.stop();
+    ctx.send(reply("Info", "Stopped and cleared the queue."))
+        .await?;
+
+    Ok(())
+}
+
+/// Skip the currently playing track, or `n` tracks starting with the current one.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    subcommands("skip_count", "skip_to", "skip_until")
+)]
+pub async fn skip(ctx: Context<'_>, n: Option<usize>) -> Result_<()> {
+    skip_tracks(ctx, n).await
+}
+
+/// Skip the currently playing track, or `n` tracks starting with the current one.
+///
+/// This is the same as `skip`, because slash commands with subcommands can't take arguments.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "count"
+)]
+pub async fn skip_count(
+    ctx: Context<'_>,
+    #[description = "How many tracks to skip"] n: Option<usize>,
+) -> Result_<()> {
+    skip_tracks(ctx, n).await
+}
+
+async fn skip_tracks(ctx: Context<'_>, n: Option<usize>) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let n = n.unwrap_or(1);
+    // Other listeners' tracks can still be skipped, if enough of them agree.
+    let queued = q.current_queue();
+    if n == 1 && !crate::permissions::may_change(ctx, &queued[..queued.len().min(1)]).await? {
+        return crate::vote::run(ctx, &q).await;
+    }
+    may_skip(ctx, &q, n).await?;
+
+    let skipped = q.skip(n).await;
+
+    ctx.send(reply("Info", format!("Skipped {skipped} track(s).")))
+        .await?;
+
+    Ok(())
+}
+
+/// Fail unless the author may skip the first `n` tracks.
+async fn may_skip(ctx: Context<'_>, q: &TrackQueue, n: usize) -> Result_<()> {
+    let queued = q.current_queue();
+    if crate::permissions::may_change(ctx, &queued[..n.min(queued.len())]).await? {
+        return Ok(());
+    }
+
+    Err(ScumboError::Permission(
+        "You can only skip tracks you requested yourself, unless you are a DJ. Use `skip` to \
+         vote on skipping the current track."
+            .into(),
+    ))
+}
+
+/// Skip to the track at `index`, as shown by `queue show`.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "to"
+)]
+pub async fn skip_to(
+    ctx: Context<'_>,
+    #[description = "Index of the track to skip to"] index: usize,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    if index < 2 || index > q.len() {
+        return Err(ScumboError::InvalidArgument(format!(
+            "There is no track at index {index} to skip to."
+        )));
+    }
+
+    may_skip(ctx, &q, index - 1).await?;
+
+    let skipped = q.skip(index - 1).await;
+    ctx.send(reply("Info", format!("Skipped {skipped} track(s).")))
+        .await?;
+
+    Ok(())
+}
+
+/// Skip to the first queued track whose title contains `title`.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "until"
+)]
+pub async fn skip_until(
+    ctx: Context<'_>,
+    #[rest]
+    #[description = "Part of the title of the track to skip to"]
+    title: String,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let needle = title.to_lowercase();
+    let Some(index) = q.find(|data| data.title().to_lowercase().contains(&needle)) else {
+        return Err(ScumboError::InvalidArgument(format!(
+            "No queued track matches `{title}`."
+        )));
+    };
+
+    may_skip(ctx, &q, index).await?;
+
+    let skipped = q.skip(index).await;
+    ctx.send(reply("Info", format!("Skipped {skipped} track(s).")))
+        .await?;
+
+    Ok(())
+}
+
+/// Set what happens with tracks after they finish playing: `off`, `track` or `queue`.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "loop"
+)]
+pub async fn loop_mode(
+    ctx: Context<'_>,
+    #[description = "The new loop mode"] mode: Option<LoopMode>,
+) -> Result_<()> {
+    let q = super::utils::voice_queue(ctx).await?;
+
+    let description = match mode {
+        Some(mode) => {
+            q.set_loop_mode(mode);
+            format!("Loop mode set to `{}`.", mode.name())
+        }
+        None => format!("Loop mode is `{}`.", q.loop_mode().name()),
+    };
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Play the previous track again right now.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    check = "crate::permissions::dj"
+)]
+pub async fn back(ctx: Context<'_>) -> Result_<()> {
+    ctx.defer().await?;
+    let q = super::utils::join_queue(ctx).await?;
+
+    let data = q.back().await.ok_or(ScumboError::NoPreviousTrack)?;
+    ctx.send(reply("Info", format!("Going back to {}.", data.title())))
+        .await?;
+
+    Ok(())
+}
+
+/// Add a track from the history, at `index` as shown by `queue history`, to the queue again.
+#[poise::command(prefix_command, slash_command, category = "Music", guild_only)]
+pub async fn replay(
+    ctx: Context<'_>,
+    #[description = "Index of the track in the history"] index: usize,
+) -> Result_<()> {
+    ctx.defer().await?;
+    let q = super::utils::join_queue(ctx).await?;
+
+    let Some(mut data) = index.checked_sub(1).and_then(|n| q.previous(n)) else {
+        return Err(ScumboError::InvalidArgument(format!(
+            "There is no track at index {index} in the history."
+        )));
+    };
+
+    q.check_full()?;
+    data.requester = Some(ctx.author().id);
+    ctx.send(reply("Info", format!("Queued {} again.", data.title())))
+        .await?;
+    q.requeue(Queued::new(data), Position::Back).await;
+
+    Ok(())
+}
+
+/// Manage your favourite tracks, which are suggested first when searching.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    subcommands("fav_add", "fav_list", "fav_remove"),
+    subcommand_required
+)]
+pub async fn fav(_: Context<'_>) -> Result_<()> {
+    Ok(())
+}
+
+/// Save the currently playing track as one of your favourites.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Music",
+    guild_only,
+    rename = "add"
+)]
+pub async fn fav_add(ctx: Context<'_>) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let current = ctx
+        .data()
+        .qs
+        .lock()
+        .get(&guild_id)
+        .and_then(|q| q.current_queue().first().cloned());
+
+    let Some(data) = current else {
+        return Err(ScumboError::NothingPlaying);
+    };
+
+    let description = if ctx
+        .data()
+        .favourites
+        .add(ctx.author().id, data.as_ref().clone())?
+    {
+        format!("Added {} to your favourites.", data.title())
+    } else {
+        format!("{} is already one of your favourites.", data.title())
+    };
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Show your favourite tracks.
+#[poise::command(prefix_command, slash_command, category = "Music", rename = "list")]
+pub async fn fav_list(ctx: Context<'_>) -> Result_<()> {
+    let favourites = ctx.data().favourites.list(ctx.author().id);
+    if favourites.is_empty() {
+        ctx.send(reply("Info", "You have no favourites yet."))
+            .await?;
+        return Ok(());
+    }
+
+    let pages = favourites
+        .chunks(10)
+        .enumerate()
+        .map(|(chunk, tracks)| {
+            tracks
+                .iter()
+                .enumerate()
+                .map(|(i, track)| format!("{}. {}\n", chunk * 10 + i + 1, track.title()))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+
+    super::utils::paginate(ctx, pages).await?;
+
+    Ok(())
+}
+
+/// Remove one of your favourites, at `index` as shown by `fav list`.
+#[poise::command(prefix_command, slash_command, category = "Music", rename = "remove")]
+pub async fn fav_remove(
+    ctx: Context<'_>,
+    #[description = "Index of the favourite"] index: usize,
+) -> Result_<()> {
+    let removed = match index.checked_sub(1) {
+        Some(i) => ctx.data().favourites.remove(ctx.author().id, i)?,
+        None => None,
+    };
+
+    let data = removed.ok_or_else(|| {
+        ScumboError::InvalidArgument(format!("There is no favourite at index {index}."))
+    })?;
+    ctx.send(reply(
+        "Info",
+        format!("Removed {} from your favourites.", data.title()),
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// View or change the settings of this server.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Admin",
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands("config_get", "config_set", "config_reset"),
+    subcommand_required
+)]
+pub async fn config(_: Context<'_>) -> Result_<()> {
+    Ok(())
+}
+
+/// Show one setting, or all of them.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Admin",
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "get"
+)]
+pub async fn config_get(
+    ctx: Context<'_>,
+    #[description = "The setting to show"] setting: Option<Setting>,
+) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let settings = ctx.data().settings.get(guild_id);
+    let config = &ctx.data().config;
+
+    let description = match setting {
+        Some(setting) => format!("`{}` is {}.", setting.name(), settings.get(setting, config)),
+        None => Setting::ALL
+            .iter()
+            .map(|&setting| format!("`{}`: {}\n", setting.name(), settings.get(setting, config)))
+            .collect(),
+    };
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Change a setting.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Admin",
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "set"
+)]
+pub async fn config_set(
+    ctx: Context<'_>,
+    #[description = "The setting to change"] setting: Setting,
+    #[rest]
+    #[description = "The new value"]
+    value: String,
+) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let data = ctx.data();
+
+    let settings = data
+        .settings
+        .update(guild_id, |settings| settings.set(setting, &value))?;
+
+    let queue = data.qs.lock().get(&guild_id).cloned();
+    if let Some(queue) = queue {
+        queue.apply_settings(&settings, &data.config);
+    }
+
+    ctx.send(reply(
+        "Info",
+        format!(
+            "`{}` is now {}.",
+            setting.name(),
+            settings.get(setting, &data.config)
+        ),
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Set one setting, or all of them, back to the default.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    category = "Admin",
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "reset"
+)]
+pub async fn config_reset(
+    ctx: Context<'_>,
+    #[description = "The setting to reset, leave empty to reset everything"] setting: Option<
+        Setting,
+    >,
+) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let data = ctx.data();
+
+    let settings = data.settings.update(guild_id, |settings| {
+        match setting {
+            Some(setting) => settings.reset(setting),
+            None => Setting::ALL
+                .iter()
+                .for_each(|&setting| settings.reset(setting)),
+        }
+        Ok(())
+    })?;
+
+    let queue = data.qs.lock().get(&guild_id).cloned();
+    if let Some(queue) = queue {
+        queue.apply_settings(&settings, &data.config);
+    }
+
+    let description = match setting {
+        Some(setting) => format!("`{}` is back to its default.", setting.name()),
+        None => "All settings are back to their defaults.".into(),
+    };
+    ctx.send(reply("Info", description)).await?;
+
+    Ok(())
+}
+
+/// Register or unregister the slash commands, either in this server or globally.
+#[poise::command(prefix_command, owners_only, hide_in_help, category = "Testing")]
+pub async fn register(ctx: Context<'_>) -> Result_<()> {
+    poise::builtins::register_application_commands_buttons(ctx).await?;
 
     Ok(())
 }
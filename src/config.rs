@@ -0,0 +1,312 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Deserializer};
+use serenity::all::UserId;
+
+/// Where the config file is looked for, unless `--config` is given.
+pub const DEFAULT_PATH: &str = "scumbo.toml";
+
+/// Printed for `--help`.
+pub const USAGE: &str = "\
+Usage: scumbo [--config <path>] [--<option> <value>]...
+
+Every option can be set in the config file, overridden by an environment variable, overridden by
+a command line flag:
+
+  token                DISCORD_TOKEN        --token                The Discord bot token.
+  owners               OWNER_IDS            --owners               Comma separated owner IDs.
+  prefix               BOT_PREFIX           --prefix               Prefix of text commands.
+  data_dir             DATA_DIR             --data-dir             Where saved data is kept.
+  history_db           HISTORY_DB           --history-db           SQLite play log, if wanted.
+  history_capacity     HISTORY_CAPACITY     --history-capacity     Tracks kept in the history.
+  preload_offset       PRELOAD_OFFSET       --preload-offset       Seconds of preloading.
+  driver_timeout       DRIVER_TIMEOUT       --driver-timeout       Seconds, 0 to disable.
+  preallocated_tracks  PREALLOCATED_TRACKS  --preallocated-tracks  Tracks allocated up front.
+  softclip             SOFTCLIP             --softclip             `true` or `false`.
+  loudness_target      LOUDNESS_TARGET      --loudness-target      LUFS, e.g. -14.
+  alone_timeout        ALONE_TIMEOUT        --alone-timeout        Seconds, 0 to disable.
+  idle_timeout         IDLE_TIMEOUT         --idle-timeout         Seconds, 0 to disable.
+";
+
+/// Environment variables which override the config file, with the option they set.
+const ENV_VARS: &[(&str, &str)] = &[
+    ("DISCORD_TOKEN", "token"),
+    ("OWNER_IDS", "owners"),
+    ("BOT_PREFIX", "prefix"),
+    ("DATA_DIR", "data_dir"),
+    ("HISTORY_DB", "history_db"),
+    ("HISTORY_CAPACITY", "history_capacity"),
+    ("PRELOAD_OFFSET", "preload_offset"),
+    ("DRIVER_TIMEOUT", "driver_timeout"),
+    ("PREALLOCATED_TRACKS", "preallocated_tracks"),
+    ("SOFTCLIP", "softclip"),
+    ("LOUDNESS_TARGET", "loudness_target"),
+    ("ALONE_TIMEOUT", "alone_timeout"),
+    ("IDLE_TIMEOUT", "idle_timeout"),
+];
+
+/// The configuration of the bot.
+///
+/// It is built in layers: the defaults, then the TOML config file, then environment variables and
+/// finally command line flags, each overriding the previous ones.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: String,
+    /// Users which may use owner only commands.
+    pub owners: Vec<UserId>,
+    pub prefix: String,
+    /// Where queue snapshots and favourites are saved.
+    pub data_dir: PathBuf,
+    /// Where the play log is kept, it is disabled if unset.
+    pub history_db: Option<PathBuf>,
+    /// How many tracks each guild keeps in its history.
+    pub history_capacity: usize,
+    /// How long before the end of a track the next one starts loading.
+    #[serde(deserialize_with = "seconds")]
+    pub preload_offset: Duration,
+    /// How long songbird waits for a voice connection, `0` disables the timeout.
+    #[serde(deserialize_with = "optional_seconds")]
+    pub driver_timeout: Option<Duration>,
+    /// How many tracks songbird allocates room for up front.
+    pub preallocated_tracks: usize,
+    /// Whether songbird soft clips the mixed audio.
+    pub softclip: bool,
+    /// Loudness tracks are normalised to, in LUFS, if a guild turns normalisation on.
+    pub loudness_target: i8,
+    /// How long the bot stays in a voice channel without listeners, `0` stays forever.
+    #[serde(deserialize_with = "optional_seconds")]
+    pub alone_timeout: Option<Duration>,
+    /// How long the bot stays in a voice channel with nothing to play, `0` stays forever.
+    #[serde(deserialize_with = "optional_seconds")]
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            owners: vec![],
+            prefix: "!".into(),
+            data_dir: "data".into(),
+            history_db: None,
+            history_capacity: 50,
+            preload_offset: Duration::from_secs(5),
+            driver_timeout: Some(Duration::from_secs(30)),
+            preallocated_tracks: 16,
+            softclip: false,
+            loudness_target: -14,
+            alone_timeout: Some(Duration::from_secs(300)),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+}
+
+fn optional_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Some(seconds(deserializer)?).filter(|timeout| !timeout.is_zero()))
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{value}` is not a valid value for `{key}`."))
+}
+
+impl Config {
+    /// Load the configuration from all layers.
+    ///
+    /// `args` are the command line arguments, without the program name.
+    pub fn load(args: &[String]) -> Result<Self, String> {
+        let flags = Self::parse_args(args)?;
+
+        let (path, required) = match flags.iter().find(|(key, _)| key == "config") {
+            Some((_, path)) => (PathBuf::from(path), true),
+            None => (PathBuf::from(DEFAULT_PATH), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(file) => Self::from_toml(&file)
+                .map_err(|e| format!("Invalid config file `{}`: {e}", path.display()))?,
+            Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(format!(
+                    "Could not read config file `{}`: {e}",
+                    path.display()
+                ));
+            }
+            Err(_) => Self::default(),
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            config
+                .set(key, value)
+                .map_err(|e| format!("Invalid flag `--{}`: {e}", key.replace('_', "-")))?;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Parse a config file.
+    pub fn from_toml(file: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(file)
+    }
+
+    /// Split the command line arguments into `--key value` or `--key=value` pairs.
+    fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, String> {
+        let mut flags = vec![];
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let flag = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("Unexpected argument `{arg}`, see `--help`."))?;
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key, value.to_string()),
+                None => (
+                    flag,
+                    args.next()
+                        .ok_or_else(|| format!("`--{flag}` needs a value."))?
+                        .clone(),
+                ),
+            };
+            flags.push((key.replace('-', "_"), value));
+        }
+
+        Ok(flags)
+    }
+
+    /// Override options with the environment variables which are set.
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        for (name, key) in ENV_VARS {
+            if let Some(value) = var(name) {
+                self.set(key, &value)
+                    .map_err(|e| format!("Invalid environment variable `{name}`: {e}"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set a single option from its textual value.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "token" => self.token = value.trim().into(),
+            "owners" => {
+                self.owners = value
+                    .split(',')
+                    .filter(|id| !id.trim().is_empty())
+                    .map(|id| match parse::<u64>(key, id)? {
+                        0 => Err("A user ID can't be 0.".to_string()),
+                        id => Ok(UserId::new(id)),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            "prefix" => self.prefix = value.into(),
+            "data_dir" => self.data_dir = value.into(),
+            "history_db" => self.history_db = Some(value.into()).filter(|_| !value.is_empty()),
+            "history_capacity" => self.history_capacity = parse(key, value)?,
+            "preload_offset" => self.preload_offset = Duration::from_secs(parse(key, value)?),
+            "driver_timeout" => {
+                self.driver_timeout =
+                    Some(Duration::from_secs(parse(key, value)?)).filter(|t| !t.is_zero())
+            }
+            "alone_timeout" => {
+                self.alone_timeout =
+                    Some(Duration::from_secs(parse(key, value)?)).filter(|t| !t.is_zero())
+            }
+            "idle_timeout" => {
+                self.idle_timeout =
+                    Some(Duration::from_secs(parse(key, value)?)).filter(|t| !t.is_zero())
+            }
+            "preallocated_tracks" => self.preallocated_tracks = parse(key, value)?,
+            "softclip" => self.softclip = parse(key, value)?,
+            "loudness_target" => self.loudness_target = parse(key, value)?,
+            _ => return Err(format!("Unknown option `{key}`, see `--help`.")),
+        }
+
+        Ok(())
+    }
+
+    /// Check that the options make sense together.
+    fn validate(&self) -> Result<(), String> {
+        if self.token.is_empty() {
+            return Err(format!(
+                "No Discord token given, set `token` in `{DEFAULT_PATH}`, `DISCORD_TOKEN` or \
+                 `--token`."
+            ));
+        }
+        if self.prefix.trim().is_empty() {
+            return Err("`prefix` can't be empty.".into());
+        }
+        if self.history_capacity == 0 {
+            return Err("`history_capacity` has to be at least 1.".into());
+        }
+        if self.preallocated_tracks == 0 {
+            return Err("`preallocated_tracks` has to be at least 1.".into());
+        }
+        if !(-70..=0).contains(&self.loudness_target) {
+            return Err("`loudness_target` has to be between -70 and 0 LUFS.".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layering() {
+        let mut config = Config::from_toml(
+            r#"
+            token = "from-file"
+            owners = [42]
+            prefix = "?"
+            preload_offset = 10
+            driver_timeout = 0
+            idle_timeout = 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.owners, vec![UserId::new(42)]);
+        assert_eq!(config.preload_offset, Duration::from_secs(10));
+        assert_eq!(config.driver_timeout, None);
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(config.alone_timeout, Some(Duration::from_secs(300)));
+        assert_eq!(config.history_capacity, 50);
+
+        config
+            .apply_env(|name| match name {
+                "BOT_PREFIX" => Some(".".into()),
+                "OWNER_IDS" => Some("1, 2".into()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.prefix, ".");
+        assert_eq!(config.owners, vec![UserId::new(1), UserId::new(2)]);
+
+        let args = ["--prefix=-", "--history-capacity", "5"].map(String::from);
+        for (key, value) in Config::parse_args(&args).unwrap() {
+            config.set(&key, &value).unwrap();
+        }
+        assert_eq!(config.prefix, "-");
+        assert_eq!(config.history_capacity, 5);
+        assert!(config.validate().is_ok());
+
+        assert!(config.set("history_capacity", "many").is_err());
+        assert!(config.set("volume", "11").is_err());
+        assert!(Config::parse_args(&["--token".into()]).is_err());
+        assert!(Config::from_toml("tokn = \"typo\"").is_err());
+        assert!(Config::default().validate().is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+
+use songbird::{
+    error::{ControlError, JoinError},
+    input::{AudioStreamError, AuxMetadataError},
+};
+use symphonia::core::errors::Error as SymphoniaError;
+
+/// Everything which can go wrong while handling a command or an event.
+///
+/// The `Display` implementation is what users get to read, so it explains what happened
+/// rather than how. Large errors are boxed, so results stay small.
+#[derive(Debug)]
+pub enum ScumboError {
+    /// The command was not used in a guild, or the guild is not cached.
+    NotInGuild,
+    /// Songbird was not registered with the client, so voice does not work at all.
+    VoiceUnavailable,
+    /// The bot is not in a voice channel of the guild.
+    NotInVoice,
+    /// The author is not in a voice channel, so the bot doesn't know where to join.
+    UserNotInVoice,
+    /// The bot is in a voice channel, but the guild has no queue.
+    QueueMissing,
+    /// The queue already holds as many tracks as it may, which is the number.
+    QueueFull(usize),
+    /// There is no current track to do anything with.
+    NothingPlaying,
+    /// The current track changed while the author was deciding about it, e.g. voting to skip it.
+    TrackChanged,
+    /// There is no track in the history to go back to.
+    NoPreviousTrack,
+    /// The play log is needed, but it is not enabled in the config.
+    PlayLogDisabled,
+    /// The channel to join is not a voice channel.
+    NotVoiceChannel,
+    /// The author's membership of the guild could not be looked up.
+    UnknownMember,
+    /// A track could not be found or fetched.
+    SourceUnavailable(AudioStreamError),
+    /// A track could be fetched, but not decoded.
+    Decode(SymphoniaError),
+    /// A request to Discord failed.
+    Http(Box<serenity::Error>),
+    /// The author may not do that.
+    Permission(String),
+    /// Waited too long, for Discord or for the author.
+    Timeout,
+    /// Joining a voice channel failed for another reason than taking too long.
+    Join(Box<JoinError>),
+    /// The current track could not be controlled, e.g. because it has already ended.
+    Control(ControlError),
+    /// Reading or writing the data directory failed.
+    Io(std::io::Error),
+    /// Reading or writing the play log failed.
+    Database(Box<rusqlite::Error>),
+    /// An argument makes no sense, explained by the message.
+    InvalidArgument(String),
+}
+
+impl fmt::Display for ScumboError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScumboError::NotInGuild => write!(f, "That only works in a server, good sir!"),
+            ScumboError::VoiceUnavailable => write!(f, "Voice is not available right now."),
+            ScumboError::NotInVoice => write!(f, "Not in a voice channel, good sir!"),
+            ScumboError::UserNotInVoice => write!(f, "You are not in a voice channel, good sir!"),
+            ScumboError::QueueMissing => {
+                write!(f, "There is no queue here, use `join` to bring me back.")
+            }
+            ScumboError::QueueFull(max) => {
+                write!(f, "The queue is full, it can hold at most {max} tracks.")
+            }
+            ScumboError::NothingPlaying => write!(f, "Nothing is playing right now."),
+            ScumboError::TrackChanged => {
+                write!(f, "The track changed, vote again to skip the new one.")
+            }
+            ScumboError::NoPreviousTrack => write!(f, "There is no previous track."),
+            ScumboError::PlayLogDisabled => {
+                write!(f, "That needs the play log, which is not enabled.")
+            }
+            ScumboError::NotVoiceChannel => write!(f, "_That_ is not a **voice** channel."),
+            ScumboError::UnknownMember => write!(f, "Could not look up your server membership."),
+            ScumboError::SourceUnavailable(e) => write!(f, "Could not get that track: {e}"),
+            ScumboError::Decode(e) => write!(f, "Could not decode that track: {e}"),
+            ScumboError::Http(e) => write!(f, "Could not talk to Discord: {e}"),
+            ScumboError::Permission(message) | ScumboError::InvalidArgument(message) => {
+                write!(f, "{message}")
+            }
+            ScumboError::Timeout => write!(f, "That took too long, please try again."),
+            ScumboError::Join(e) => write!(f, "Could not join the voice channel because: {e}"),
+            ScumboError::Control(e) => write!(f, "Could not control the track: {e}"),
+            ScumboError::Io(e) => write!(f, "Could not save or load data: {e}"),
+            ScumboError::Database(e) => write!(f, "Could not use the play log: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScumboError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScumboError::SourceUnavailable(e) => Some(e),
+            ScumboError::Decode(e) => Some(e),
+            ScumboError::Http(e) => Some(e),
+            ScumboError::Join(e) => Some(e),
+            ScumboError::Control(e) => Some(e),
+            ScumboError::Io(e) => Some(e),
+            ScumboError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<AudioStreamError> for ScumboError {
+    fn from(e: AudioStreamError) -> Self {
+        ScumboError::SourceUnavailable(e)
+    }
+}
+
+impl From<AuxMetadataError> for ScumboError {
+    fn from(e: AuxMetadataError) -> Self {
+        match e {
+            AuxMetadataError::Retrieve(e) => ScumboError::SourceUnavailable(e),
+            e => ScumboError::SourceUnavailable(AudioStreamError::Fail(e.to_string().into())),
+        }
+    }
+}
+
+impl From<SymphoniaError> for ScumboError {
+    fn from(e: SymphoniaError) -> Self {
+        ScumboError::Decode(e)
+    }
+}
+
+impl From<serenity::Error> for ScumboError {
+    fn from(e: serenity::Error) -> Self {
+        ScumboError::Http(Box::new(e))
+    }
+}
+
+impl From<JoinError> for ScumboError {
+    fn from(e: JoinError) -> Self {
+        match e {
+            JoinError::TimedOut => ScumboError::Timeout,
+            e => ScumboError::Join(Box::new(e)),
+        }
+    }
+}
+
+impl From<ControlError> for ScumboError {
+    fn from(e: ControlError) -> Self {
+        ScumboError::Control(e)
+    }
+}
+
+impl From<std::io::Error> for ScumboError {
+    fn from(e: std::io::Error) -> Self {
+        ScumboError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ScumboError {
+    fn from(e: serde_json::Error) -> Self {
+        ScumboError::Io(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for ScumboError {
+    fn from(e: rusqlite::Error) -> Self {
+        ScumboError::Database(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert!(matches!(
+            ScumboError::from(JoinError::TimedOut),
+            ScumboError::Timeout
+        ));
+        assert!(matches!(
+            ScumboError::from(JoinError::NoCall),
+            ScumboError::Join(_)
+        ));
+        assert_eq!(
+            ScumboError::QueueFull(3).to_string(),
+            "The queue is full, it can hold at most 3 tracks."
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use parking_lot::Mutex;
+use serenity::all::UserId;
+
+use crate::{Result_, history::TrackUserData};
+
+/// Tracks saved by users, so that they can easily find them again.
+///
+/// The favourites of all users are kept in a single JSON file, which is rewritten on every change.
+#[derive(Debug)]
+pub struct Favourites {
+    path: PathBuf,
+    tracks: Mutex<HashMap<UserId, Vec<TrackUserData>>>,
+}
+
+impl Favourites {
+    /// Load the favourites stored at `path`, starting with none if the file doesn't exist.
+    pub fn load(path: impl Into<PathBuf>) -> Result_<Self> {
+        let path = path.into();
+        let tracks = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            path,
+            tracks: Mutex::new(tracks),
+        })
+    }
+
+    fn save(&self, tracks: &HashMap<UserId, Vec<TrackUserData>>) -> Result_<()> {
+        crate::persist::write_atomic(&self.path, &serde_json::to_vec(tracks)?)?;
+
+        Ok(())
+    }
+
+    /// Save a track as one of the user's favourites.
+    ///
+    /// Returns `false` if the track was already saved.
+    pub fn add(&self, user: UserId, data: TrackUserData) -> Result_<bool> {
+        let mut tracks = self.tracks.lock();
+        let saved = tracks.entry(user).or_default();
+
+        if saved.iter().any(|track| track.url() == data.url()) {
+            return Ok(false);
+        }
+        saved.push(data);
+        self.save(&tracks)?;
+
+        Ok(true)
+    }
+
+    /// Remove the user's favourite at `index`.
+    pub fn remove(&self, user: UserId, index: usize) -> Result_<Option<TrackUserData>> {
+        let mut tracks = self.tracks.lock();
+        let Some(saved) = tracks.get_mut(&user).filter(|saved| index < saved.len()) else {
+            return Ok(None);
+        };
+
+        let removed = saved.remove(index);
+        self.save(&tracks)?;
+
+        Ok(Some(removed))
+    }
+
+    /// Get all favourites of the user.
+    pub fn list(&self, user: UserId) -> Vec<TrackUserData> {
+        self.tracks.lock().get(&user).cloned().unwrap_or_default()
+    }
+}
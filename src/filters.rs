@@ -0,0 +1,725 @@
+use std::{
+    f64::consts::{FRAC_PI_4, PI, SQRT_2},
+    io::{self, Read, Seek, SeekFrom},
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use songbird::input::{
+    AudioStream, AudioStreamError, AuxMetadata, Compose, Input, LiveInput, Parsed, RawAdapter,
+    codecs::{get_codec_registry, get_probe},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    errors::Error as SymphoniaError,
+    formats::{SeekMode, SeekTo},
+    io::MediaSource,
+    units::Time,
+};
+
+use crate::history::TrackUserData;
+
+/// Filtered audio is always stereo, so that it can be panned.
+const CHANNELS: usize = 2;
+
+/// Size of one frame of the raw `f32` PCM handed to songbird.
+const FRAME_BYTES: u64 = (CHANNELS * size_of::<f32>()) as u64;
+
+/// Length of the header `RawAdapter` puts in front of the PCM.
+const RAW_HEADER: u64 = 16;
+
+/// A second order IIR filter, in direct form I.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Create a filter from its coefficients, normalised so that `a0` is 1.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            ..Default::default()
+        }
+    }
+
+    /// A bell around `frequency`, see the Audio EQ Cookbook.
+    fn peaking(sample_rate: u32, frequency: f64, gain_db: f64, q: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / f64::from(sample_rate);
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha / a;
+
+        Self::new(
+            [
+                (1.0 + alpha * a) / a0,
+                -2.0 * w0.cos() / a0,
+                (1.0 - alpha * a) / a0,
+            ],
+            [-2.0 * w0.cos() / a0, (1.0 - alpha / a) / a0],
+        )
+    }
+
+    /// A shelf changing everything below `frequency`, see the Audio EQ Cookbook.
+    fn low_shelf(sample_rate: u32, frequency: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / f64::from(sample_rate);
+        let (cos, alpha) = (w0.cos(), w0.sin() / SQRT_2);
+        let root = 2.0 * a.sqrt() * alpha;
+        let a0 = (a + 1.0) + (a - 1.0) * cos + root;
+
+        Self::new(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + root) / a0,
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos) / a0,
+                a * ((a + 1.0) - (a - 1.0) * cos - root) / a0,
+            ],
+            [
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos) / a0,
+                ((a + 1.0) + (a - 1.0) * cos - root) / a0,
+            ],
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Which part of the chain a filter takes up, only one filter per slot can be enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Slot {
+    Speed,
+    Equalizer,
+    Bass,
+    Panner,
+}
+
+/// An audio filter which can be enabled in a guild.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter)]
+pub enum Filter {
+    #[name = "pop"]
+    Pop,
+    #[name = "rock"]
+    Rock,
+    #[name = "vocal"]
+    Vocal,
+    #[name = "bassboost"]
+    BassBoost,
+    #[name = "nightcore"]
+    Nightcore,
+    #[name = "vaporwave"]
+    Vaporwave,
+    #[name = "8d"]
+    EightD,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 7] = [
+        Filter::Pop,
+        Filter::Rock,
+        Filter::Vocal,
+        Filter::BassBoost,
+        Filter::Nightcore,
+        Filter::Vaporwave,
+        Filter::EightD,
+    ];
+
+    pub fn slot(self) -> Slot {
+        match self {
+            Filter::Pop | Filter::Rock | Filter::Vocal => Slot::Equalizer,
+            Filter::BassBoost => Slot::Bass,
+            Filter::Nightcore | Filter::Vaporwave => Slot::Speed,
+            Filter::EightD => Slot::Panner,
+        }
+    }
+
+    /// A short explanation of what the filter does.
+    pub fn description(self) -> &'static str {
+        match self {
+            Filter::Pop => "Equalizer with brighter mids.",
+            Filter::Rock => "Equalizer with punchy lows and highs.",
+            Filter::Vocal => "Equalizer bringing out voices.",
+            Filter::BassBoost => "More bass.",
+            Filter::Nightcore => "Faster and higher.",
+            Filter::Vaporwave => "Slower and lower.",
+            Filter::EightD => "The sound circles around your head.",
+        }
+    }
+
+    /// Bands of the equalizer presets, as frequency, gain in dB and Q.
+    fn bands(self) -> &'static [(f64, f64, f64)] {
+        match self {
+            Filter::Pop => &[
+                (100.0, -1.0, 1.0),
+                (500.0, 2.0, 1.0),
+                (1500.0, 3.0, 1.0),
+                (5000.0, 2.0, 1.0),
+                (12000.0, -1.0, 1.0),
+            ],
+            Filter::Rock => &[
+                (80.0, 4.0, 0.8),
+                (400.0, -2.0, 1.0),
+                (2500.0, 2.0, 1.0),
+                (8000.0, 4.0, 0.8),
+            ],
+            Filter::Vocal => &[
+                (150.0, -3.0, 0.8),
+                (1000.0, 2.0, 1.0),
+                (3000.0, 4.0, 1.0),
+                (10000.0, -1.0, 1.0),
+            ],
+            _ => &[],
+        }
+    }
+
+    /// How fast the audio is played, which changes the pitch with it.
+    fn speed(self) -> f64 {
+        match self {
+            Filter::Nightcore => 1.25,
+            Filter::Vaporwave => 0.8,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Changes the speed, and with it the pitch, by resampling with linear interpolation.
+#[derive(Clone, Debug)]
+struct Resampler {
+    speed: f64,
+    /// Position of the next output frame, where `0.0` is the last frame of the previous chunk.
+    position: f64,
+    previous: [f32; CHANNELS],
+}
+
+impl Resampler {
+    fn new(speed: f64) -> Self {
+        Self {
+            speed,
+            position: 1.0,
+            previous: [0.0; CHANNELS],
+        }
+    }
+
+    fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let frames = input.len() / CHANNELS;
+        let frame = |i: usize| match i {
+            0 => &self.previous[..],
+            i => &input[(i - 1) * CHANNELS..i * CHANNELS],
+        };
+
+        let mut output = Vec::with_capacity((frames as f64 / self.speed) as usize * CHANNELS + 2);
+        while self.position < frames as f64 {
+            let i = self.position as usize;
+            let t = (self.position - i as f64) as f32;
+            let (a, b) = (frame(i), frame(i + 1));
+            output.extend((0..CHANNELS).map(|c| a[c] + (b[c] - a[c]) * t));
+            self.position += self.speed;
+        }
+
+        self.position -= frames as f64;
+        if frames > 0 {
+            self.previous
+                .copy_from_slice(&input[(frames - 1) * CHANNELS..]);
+        }
+        output
+    }
+}
+
+/// Moves the sound around the listener, on a mono mix of the audio.
+#[derive(Clone, Debug)]
+struct Panner {
+    /// Full circles per second.
+    rate: f64,
+    sample_rate: f64,
+    frame: u64,
+}
+
+impl Panner {
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            let angle = 2.0 * PI * self.rate * self.frame as f64 / self.sample_rate;
+            // Equal power panning, which keeps the level when the sound is in the centre.
+            let pan = (angle.sin() + 1.0) * FRAC_PI_4;
+            let mid = f64::from(frame[0] + frame[1]) / 2.0;
+            frame[0] = (mid * pan.cos() * SQRT_2) as f32;
+            frame[1] = (mid * pan.sin() * SQRT_2) as f32;
+            self.frame += 1;
+        }
+    }
+}
+
+/// The state of all enabled filters for one track.
+#[derive(Clone, Debug)]
+pub struct Chain {
+    /// One set of equalizer and bass filters per channel.
+    biquads: Vec<Vec<Biquad>>,
+    resampler: Option<Resampler>,
+    panner: Option<Panner>,
+}
+
+impl Chain {
+    pub fn new(filters: &[Filter], sample_rate: u32) -> Self {
+        let mut biquads = vec![];
+        let mut resampler = None;
+        let mut panner = None;
+
+        for &filter in filters {
+            match filter.slot() {
+                Slot::Speed => resampler = Some(Resampler::new(filter.speed())),
+                Slot::Equalizer => biquads.extend(
+                    filter
+                        .bands()
+                        .iter()
+                        .map(|&(f, gain, q)| Biquad::peaking(sample_rate, f, gain, q)),
+                ),
+                Slot::Bass => biquads.push(Biquad::low_shelf(sample_rate, 120.0, 8.0)),
+                Slot::Panner => {
+                    panner = Some(Panner {
+                        rate: 0.125,
+                        sample_rate: f64::from(sample_rate),
+                        frame: 0,
+                    })
+                }
+            }
+        }
+
+        Self {
+            biquads: vec![biquads; CHANNELS],
+            resampler,
+            panner,
+        }
+    }
+
+    /// Is audio passed through unchanged?
+    pub fn is_empty(&self) -> bool {
+        self.biquads[0].is_empty() && self.resampler.is_none() && self.panner.is_none()
+    }
+
+    /// Run interleaved stereo samples through all filters.
+    ///
+    /// A speed change makes the output shorter or longer than the input.
+    pub fn process(&mut self, samples: Vec<f32>) -> Vec<f32> {
+        if self.is_empty() {
+            return samples;
+        }
+
+        let mut samples = match &mut self.resampler {
+            Some(resampler) => resampler.process(&samples),
+            None => samples,
+        };
+
+        if !self.biquads[0].is_empty() {
+            for frame in samples.chunks_exact_mut(CHANNELS) {
+                for (sample, biquads) in frame.iter_mut().zip(&mut self.biquads) {
+                    let filtered = biquads
+                        .iter_mut()
+                        .fold(f64::from(*sample), |x, biquad| biquad.process(x));
+                    *sample = filtered as f32;
+                }
+            }
+        }
+
+        if let Some(panner) = &mut self.panner {
+            panner.process(&mut samples);
+        }
+
+        samples
+    }
+
+    /// How much faster than normal the audio plays.
+    fn speed(&self) -> f64 {
+        self.resampler
+            .as_ref()
+            .map_or(1.0, |resampler| resampler.speed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Enabled {
+    filters: Vec<Filter>,
+    /// Changes every time the filters change, so that playing tracks notice.
+    version: u64,
+}
+
+/// The filters enabled in a guild, shared with the tracks which are playing.
+#[derive(Clone, Debug, Default)]
+pub struct Filters(Arc<Mutex<Enabled>>);
+
+impl Filters {
+    /// Get the enabled filters, in the order they are applied.
+    pub fn list(&self) -> Vec<Filter> {
+        self.0.lock().filters.clone()
+    }
+
+    /// Are no filters enabled?
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().filters.is_empty()
+    }
+
+    /// How much faster than normal the enabled filters make the audio play.
+    pub fn speed(&self) -> f64 {
+        self.0.lock().filters.iter().map(|f| f.speed()).product()
+    }
+
+    /// Enable a filter, returning the filter it replaces, if any.
+    pub fn enable(&self, filter: Filter) -> Option<Filter> {
+        let mut enabled = self.0.lock();
+        let replaced = enabled
+            .filters
+            .iter()
+            .position(|f| f.slot() == filter.slot())
+            .map(|i| enabled.filters.remove(i));
+
+        enabled.filters.push(filter);
+        enabled.filters.sort_by_key(|f| f.slot());
+        enabled.version += 1;
+
+        replaced
+    }
+
+    /// Disable a filter, returns whether it was enabled.
+    pub fn disable(&self, filter: Filter) -> bool {
+        let mut enabled = self.0.lock();
+        let before = enabled.filters.len();
+        enabled.filters.retain(|&f| f != filter);
+        enabled.version += 1;
+
+        enabled.filters.len() != before
+    }
+
+    /// Disable all filters.
+    pub fn clear(&self) {
+        let mut enabled = self.0.lock();
+        enabled.filters.clear();
+        enabled.version += 1;
+    }
+
+    fn version(&self) -> u64 {
+        self.0.lock().version
+    }
+}
+
+/// A track whose decoded audio is run through the filters of its guild before songbird mixes it.
+pub struct FilteredInput {
+    data: Arc<TrackUserData>,
+    client: reqwest::Client,
+    filters: Filters,
+}
+
+impl FilteredInput {
+    pub fn new(data: Arc<TrackUserData>, client: reqwest::Client, filters: Filters) -> Self {
+        Self {
+            data,
+            client,
+            filters,
+        }
+    }
+}
+
+impl From<FilteredInput> for Input {
+    fn from(input: FilteredInput) -> Self {
+        Input::Lazy(Box::new(input))
+    }
+}
+
+#[async_trait]
+impl Compose for FilteredInput {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let input = self
+            .data
+            .input(self.client.clone())
+            .make_playable_async(get_codec_registry(), get_probe())
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?;
+        let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+            return Err(AudioStreamError::Unsupported);
+        };
+
+        let filters = self.filters.clone();
+        let source = tokio::task::spawn_blocking(move || FilterSource::new(parsed, filters))
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?;
+        let sample_rate = source.sample_rate;
+
+        Ok(AudioStream {
+            input: Box::new(RawAdapter::new(source, sample_rate, CHANNELS as u32)),
+            hint: None,
+        })
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+
+    async fn aux_metadata(&mut self) -> Result<AuxMetadata, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+}
+
+/// Decodes a track and runs it through the filters, producing raw interleaved `f32` stereo PCM.
+struct FilterSource {
+    parsed: Parsed,
+    filters: Filters,
+    version: u64,
+    chain: Chain,
+    sample_rate: u32,
+    buffer: Option<SampleBuffer<f32>>,
+    /// Filtered PCM which was not read yet.
+    pending: Vec<u8>,
+    read: usize,
+}
+
+impl FilterSource {
+    /// Start decoding, the first packet tells the sample rate.
+    fn new(parsed: Parsed, filters: Filters) -> Result<Self, SymphoniaError> {
+        let mut source = Self {
+            parsed,
+            version: filters.version(),
+            chain: Chain::new(&filters.list(), 48000),
+            filters,
+            sample_rate: 0,
+            buffer: None,
+            pending: vec![],
+            read: 0,
+        };
+
+        source.sample_rate = match source.parsed.decoder.codec_params().sample_rate {
+            Some(rate) => rate,
+            None => {
+                if !source.decode_next()? {
+                    return Err(SymphoniaError::DecodeError("the track has no audio"));
+                }
+                source.sample_rate
+            }
+        };
+        source.chain = Chain::new(&source.filters.list(), source.sample_rate);
+
+        Ok(source)
+    }
+
+    /// Decode and filter the next packet into `pending`, returns `false` at the end.
+    fn decode_next(&mut self) -> Result<bool, SymphoniaError> {
+        loop {
+            let packet = match self.parsed.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(false);
+                }
+                Err(e) => return Err(e),
+            };
+            if packet.track_id() != self.parsed.track_id {
+                continue;
+            }
+
+            let decoded = match self.parsed.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A broken packet can be skipped.
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let spec = *decoded.spec();
+            if self.sample_rate == 0 {
+                self.sample_rate = spec.rate;
+            }
+            if self
+                .buffer
+                .as_ref()
+                .is_none_or(|buffer| buffer.capacity() < decoded.capacity())
+            {
+                self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let buffer = self.buffer.as_mut().expect("Was just created.");
+            buffer.copy_interleaved_ref(decoded);
+
+            // Mono is spread to both channels, anything beyond stereo is dropped.
+            let channels = spec.channels.count().max(1);
+            let stereo = buffer
+                .samples()
+                .chunks_exact(channels)
+                .flat_map(|frame| [frame[0], frame[frame.len().min(2) - 1]])
+                .collect();
+
+            let version = self.filters.version();
+            if version != self.version {
+                self.version = version;
+                self.chain = Chain::new(&self.filters.list(), self.sample_rate);
+            }
+            let filtered = self.chain.process(stereo);
+
+            self.pending.drain(..self.read);
+            self.read = 0;
+            self.pending
+                .extend(filtered.iter().flat_map(|sample| sample.to_le_bytes()));
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for FilterSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.pending.len() {
+            if !self.decode_next().map_err(io::Error::other)? {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.pending.len() - self.read);
+        buf[..n].copy_from_slice(&self.pending[self.read..][..n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+impl Seek for FilterSource {
+    /// Seek to a byte of the PCM, `RawAdapter` counts its header in.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let SeekFrom::Start(byte) = pos else {
+            return Err(io::ErrorKind::Unsupported.into());
+        };
+        let byte = byte.saturating_sub(RAW_HEADER);
+
+        // The position of the filtered audio is mapped back with the current speed.
+        let seconds =
+            (byte / FRAME_BYTES) as f64 / f64::from(self.sample_rate) * self.chain.speed();
+        self.parsed
+            .format
+            .seek(
+                SeekMode::Coarse,
+                SeekTo::Time {
+                    time: Time::from(Duration::from_secs_f64(seconds)),
+                    track_id: Some(self.parsed.track_id),
+                },
+            )
+            .map_err(io::Error::other)?;
+
+        self.parsed.decoder.reset();
+        self.chain = Chain::new(&self.filters.list(), self.sample_rate);
+        self.pending.clear();
+        self.read = 0;
+
+        Ok(byte)
+    }
+}
+
+impl MediaSource for FilterSource {
+    fn is_seekable(&self) -> bool {
+        self.parsed.supports_backseek
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    fn sine(frequency: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let sample =
+                    0.5 * (2.0 * std::f32::consts::PI * frequency * i as f32 / RATE as f32).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn bass_boost() {
+        let mut chain = Chain::new(&[Filter::BassBoost], RATE);
+        // Skip the first part, while the filter settles.
+        let low = chain.process(sine(50.0, RATE as usize));
+        let mut chain = Chain::new(&[Filter::BassBoost], RATE);
+        let high = chain.process(sine(5000.0, RATE as usize));
+
+        let settled = RATE as usize;
+        assert!(rms(&low[settled..]) > 2.0 * rms(&sine(50.0, RATE as usize)));
+        assert!((rms(&high[settled..]) - rms(&sine(5000.0, RATE as usize))).abs() < 0.01);
+    }
+
+    #[test]
+    fn speed() {
+        let input = sine(440.0, RATE as usize);
+
+        // Processing in chunks gives the same length as in one go.
+        let mut chain = Chain::new(&[Filter::Nightcore], RATE);
+        let output: Vec<_> = input
+            .chunks(960 * CHANNELS)
+            .flat_map(|chunk| chain.process(chunk.to_vec()))
+            .collect();
+        let frames = output.len() / CHANNELS;
+        assert!(frames.abs_diff(RATE as usize * 4 / 5) <= 1, "{frames}");
+
+        let mut chain = Chain::new(&[Filter::Vaporwave], RATE);
+        let frames = chain.process(input.clone()).len() / CHANNELS;
+        assert!(frames.abs_diff(RATE as usize * 5 / 4) <= 1, "{frames}");
+
+        // Without filters, the audio passes through untouched.
+        let mut chain = Chain::new(&[], RATE);
+        assert!(chain.is_empty());
+        assert_eq!(chain.process(input.clone()), input);
+    }
+
+    #[test]
+    fn panner() {
+        let mut chain = Chain::new(&[Filter::EightD], RATE);
+        // A quarter of a circle in, the sound is fully on one side.
+        let frames = RATE as usize * 2;
+        let output = chain.process(vec![0.5; frames * CHANNELS]);
+        let frame = &output[(frames - 1) * CHANNELS..];
+        assert!(frame[0].abs() < 0.01, "{frame:?}");
+        assert!((frame[1] - 0.5 * SQRT_2 as f32).abs() < 0.01, "{frame:?}");
+
+        // In the centre, the level stays the same.
+        assert!((output[0] - 0.5).abs() < 0.01);
+        assert!((output[1] - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn slots() {
+        let filters = Filters::default();
+        assert!(filters.is_empty());
+        assert_eq!(filters.speed(), 1.0);
+        assert_eq!(filters.enable(Filter::EightD), None);
+        assert_eq!(filters.enable(Filter::Pop), None);
+        assert_eq!(filters.enable(Filter::Nightcore), None);
+        assert_eq!(filters.enable(Filter::Rock), Some(Filter::Pop));
+        assert_eq!(
+            filters.list(),
+            vec![Filter::Nightcore, Filter::Rock, Filter::EightD]
+        );
+        assert_eq!(filters.speed(), 1.25);
+
+        assert!(filters.disable(Filter::Rock));
+        assert!(!filters.disable(Filter::Rock));
+        filters.clear();
+        assert!(filters.is_empty());
+    }
+}
@@ -1,13 +1,18 @@
 use serenity::{
-    all::{ChannelId, CreateEmbed, CreateMessage, Http},
+    all::{ChannelId, CreateEmbed, CreateMessage, GuildId, Http},
     async_trait,
 };
-use songbird::{Event, EventContext, EventHandler as VoiceEventHandler};
-use std::sync::Arc;
+use songbird::{
+    Event, EventContext, EventHandler as VoiceEventHandler, Songbird,
+    events::context_data::DisconnectReason, model::CloseCode,
+};
+use std::{sync::Arc, time::Duration};
 
 use crate::{
     history::TrackUserData,
-    queue::{QueueHandler, SongPreloader},
+    inactivity::{Inactivity, Reason},
+    queue::{LoopMode, Position, QueueHandler, Queued, SongPreloader, TrackQueue, Transition},
+    settings::Settings,
 };
 
 pub struct TrackErrorHandler;
@@ -32,32 +37,81 @@ impl VoiceEventHandler for TrackErrorHandler {
 #[async_trait]
 impl VoiceEventHandler for QueueHandler {
     async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        let mut inner = self.remote_lock.lock();
+        let EventContext::Track(ts) = ctx else {
+            return None;
+        };
+        let (state, handle) = ts.first()?;
 
-        match ctx {
-            EventContext::Track(ts) => {
-                if inner.queued_tracks.front()?.uuid() != ts.first()?.1.uuid() {
-                    return None;
-                }
+        let (requeued, fade_in) = {
+            let mut inner = self.remote_lock.lock();
+
+            let is_front = inner
+                .queued_tracks
+                .front()
+                .and_then(Queued::handle)
+                .is_some_and(|front| front.uuid() == handle.uuid());
+            let skipped = inner.take_skipped(handle);
+
+            // Every track which was actually listened to ends up in the play log, even the
+            // skipped ones, but not those which were only stopped to be built again, e.g. by
+            // `back`. Writing it blocks, so it happens on the blocking thread pool.
+            if (is_front || skipped)
+                && let Some(log) = inner.play_log.clone()
+                && !state.play_time.is_zero()
+            {
+                let (guild_id, play_time) = (inner.guild_id, state.play_time);
+                let data = handle.data::<TrackUserData>();
+                drop(tokio::task::spawn_blocking(move || {
+                    if let Err(e) = log.record(guild_id, data.requester(), &data, play_time) {
+                        println!("could not record a played track: {e}");
+                    }
+                }));
             }
-            _ => return None,
-        }
 
-        let old = inner.queued_tracks.pop_front();
-        if let Some(track) = old {
-            inner
-                .history
-                .add(Arc::unwrap_or_clone(track.data::<TrackUserData>()))
-        }
+            if !is_front {
+                return None;
+            }
 
-        // Keep going until we find one track which works, or we run out.
-        while let Some(new) = inner.queued_tracks.front() {
-            if new.play().is_err() {
-                // Discard files which cannot be used for whatever reason.
-                inner.queued_tracks.pop_front();
-            } else {
-                break;
+            // Usually the preloaded track was started on time already. Tracks of unknown length
+            // could not be timed, so it is at least started right away, without waiting for the
+            // driver.
+            if inner.transition == Transition::Gapless
+                && inner.loop_mode != LoopMode::Track
+                && let Some(next) = inner.queued_tracks.get(1).and_then(Queued::handle)
+            {
+                drop(next.play());
             }
+
+            // Ended tracks can't be replayed, so a new entry is created from the same recipe.
+            let front = inner.queued_tracks.front()?;
+            let fresh = front.fresh();
+            inner.advance(1);
+            inner.take_skipped(handle);
+
+            // Without a duration, the crossfade could not start early, so the next track at
+            // least fades in.
+            let fade_in = inner
+                .transition
+                .crossfade()
+                .filter(|_| handle.data::<TrackUserData>().duration.is_none());
+
+            // `advance` already put the track back at the end when the whole queue loops.
+            let requeued = match inner.loop_mode {
+                LoopMode::Off | LoopMode::Queue => None,
+                LoopMode::Track => Some((fresh, Position::Front)),
+            };
+            (requeued, fade_in)
+        };
+
+        let queue = TrackQueue {
+            inner: self.remote_lock.clone(),
+        };
+        match requeued {
+            Some((fresh, position)) => queue.requeue(fresh, position).await,
+            None => queue.play_front().await,
+        }
+        if let Some(length) = fade_in {
+            tokio::spawn(async move { queue.fade_in(length).await });
         }
 
         None
@@ -67,38 +121,207 @@ impl VoiceEventHandler for QueueHandler {
 #[async_trait]
 impl VoiceEventHandler for SongPreloader {
     async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
-        let inner = self.remote_lock.lock();
+        let queue = TrackQueue {
+            inner: self.remote_lock.clone(),
+        };
+        queue.preload_next().await;
+        // The crossfade waits for the end of the track, which must not hold up other events.
+        tokio::spawn(async move { queue.crossfade().await });
+
+        None
+    }
+}
+
+/// How often the position of the current track is remembered.
+pub const POSITION_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the connection is tried to be restored, before giving up.
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first attempt to reconnect, it doubles with every attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Brings the voice connection back after Discord dropped it, and continues playing where the
+/// listeners last heard the current track.
+///
+/// If that fails, the bot leaves for good, which makes Discord send a voice state update that
+/// cleans up the queue.
+#[derive(Clone)]
+pub struct ConnectionHandler {
+    pub guild_id: GuildId,
+    pub songbird: Arc<Songbird>,
+    pub http: Arc<Http>,
+    pub settings: Settings,
+    pub queue: TrackQueue,
+}
+
+impl ConnectionHandler {
+    /// Try to join `channel_id` again, waiting longer after every failed attempt.
+    async fn reconnect(self, channel_id: songbird::id::ChannelId) {
+        let mut delay = RECONNECT_DELAY;
+
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+
+            // The bot left in the meantime, e.g. with `leave`.
+            if self.songbird.get(self.guild_id).is_none() {
+                return;
+            }
+
+            match self.songbird.join(self.guild_id, channel_id).await {
+                Ok(_) => {
+                    println!("reconnected to the voice channel in {}", self.guild_id);
+                    self.queue.recover().await;
+                    return;
+                }
+                Err(e) => println!(
+                    "could not reconnect in {}, attempt {attempt}: {e}",
+                    self.guild_id
+                ),
+            }
+        }
+
+        self.give_up().await;
+    }
+
+    /// Leave for good, unless that already happened.
+    async fn leave(&self) {
+        if self.songbird.get(self.guild_id).is_some() {
+            drop(self.songbird.remove(self.guild_id).await);
+        }
+    }
+
+    /// Leave for good and tell the guild why the music stopped.
+    async fn give_up(&self) {
+        let channel = self
+            .settings
+            .get(self.guild_id)
+            .announce_channel
+            .or_else(|| self.queue.panel().map(|(channel, _)| channel));
+
+        self.leave().await;
+
+        if let Some(channel) = channel {
+            let embed = CreateEmbed::new().title("Error").description(
+                "I lost the voice connection and could not get it back. Use `join` to bring me \
+                 back, the tracks are in the history.",
+            );
+            if let Err(e) = channel
+                .send_message(&self.http, CreateMessage::new().embed(embed))
+                .await
+            {
+                println!("could not announce the lost voice connection: {e}");
+            }
+        }
+    }
+}
 
-        if let Some(track) = inner.queued_tracks.get(1) {
-            // This is the sync-version so that we can fire and ignore
-            drop(track.0.make_playable());
+#[async_trait]
+impl VoiceEventHandler for ConnectionHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let disconnect = match ctx {
+            EventContext::DriverDisconnect(disconnect) => disconnect,
+            // Songbird reconnected on its own, the tracks played on in the meantime.
+            EventContext::DriverReconnect(_) => {
+                self.queue.recover().await;
+                return None;
+            }
+            _ => return None,
+        };
+
+        // Disconnects without a reason were asked for, e.g. by `leave`.
+        let reason = disconnect.reason?;
+        if reason == DisconnectReason::Requested {
+            return None;
+        }
+        println!("lost the voice connection in {}: {reason:?}", self.guild_id);
+        self.queue.lost_connection();
+
+        // Kicked out, or the channel was deleted, so there is nothing to go back to.
+        let kicked = reason == DisconnectReason::WsClosed(Some(CloseCode::Disconnected));
+        match disconnect.channel_id {
+            _ if kicked => self.leave().await,
+            // Waiting between attempts must not hold up other events.
+            Some(channel_id) => drop(tokio::spawn(self.clone().reconnect(channel_id))),
+            None => self.give_up().await,
         }
 
         None
     }
 }
 
-pub struct ResumeHandler(pub (ChannelId, Http));
+/// Starts the idle timer whenever a track ends, it only runs out if nothing plays after it.
+pub struct IdleHandler {
+    pub guild_id: GuildId,
+    pub inactivity: Inactivity,
+}
+
+#[async_trait]
+impl VoiceEventHandler for IdleHandler {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.inactivity.restart(self.guild_id, Reason::Idle);
+
+        None
+    }
+}
+
+/// Shows every track which starts playing, or resumes, on the now playing panel.
+pub struct ResumeHandler {
+    pub guild_id: GuildId,
+    /// Used if the guild has no announce channel set.
+    pub channel_id: ChannelId,
+    pub http: Arc<Http>,
+    pub settings: Settings,
+    pub queue: TrackQueue,
+    pub inactivity: Inactivity,
+}
 
 #[async_trait]
 impl VoiceEventHandler for ResumeHandler {
     async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        match ctx {
-            EventContext::Track(track) => {
-                if let Some((_, handle)) = track.first() {
-                    let title = handle.data::<TrackUserData>().title();
-                    let (channel_id, http) = &self.0;
-                    let _ = channel_id
-                        .send_message(
-                            http,
-                            CreateMessage::new()
-                                .embed(CreateEmbed::new().title("Now playing").description(title)),
-                        )
-                        .await;
-                };
-                return None;
-            }
-            _ => None,
+        if let EventContext::Track(track) = ctx
+            && !track.is_empty()
+        {
+            self.inactivity.cancel(self.guild_id, Reason::Idle);
+
+            let channel_id = self
+                .settings
+                .get(self.guild_id)
+                .announce_channel
+                .unwrap_or(self.channel_id);
+            crate::panel::show(&self.http, &self.queue, channel_id).await;
         }
+
+        None
+    }
+}
+
+/// Remembers the position of the current track, to continue from after a lost connection.
+pub struct PositionTracker {
+    pub queue: TrackQueue,
+}
+
+#[async_trait]
+impl VoiceEventHandler for PositionTracker {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.queue.track_position().await;
+
+        None
+    }
+}
+
+/// Moves the progress bar of the now playing panel along.
+pub struct PanelRefresher {
+    pub http: Arc<Http>,
+    pub queue: TrackQueue,
+}
+
+#[async_trait]
+impl VoiceEventHandler for PanelRefresher {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        crate::panel::refresh(&self.http, &self.queue).await;
+
+        None
     }
 }
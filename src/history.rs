@@ -1,5 +1,12 @@
 #![allow(unused)]
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::UserId;
+use songbird::input::{HttpRequest, Input, YoutubeDl};
 
 /// A fixed sized buffer for holding up to `capacity` data about tracks played in a single server.
 ///
@@ -28,6 +35,14 @@ impl History {
         }
     }
 
+    /// Change the capacity, forgetting the oldest tracks if there are too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.tracks.len() > capacity {
+            self.tracks.pop_front();
+        }
+    }
+
     /// Add info about a track into the track data.
     pub fn add(&mut self, data: TrackUserData) {
         // TODO: Think about how duplicate `TrackUserData` should be handled.
@@ -40,7 +55,7 @@ impl History {
 
     /// Get the latest song added to history.
     pub fn remove(&mut self) -> Option<TrackUserData> {
-        self.tracks.pop_front()
+        self.tracks.pop_back()
     }
 
     /// Get the `n-th` previous `TrackUserData`.
@@ -59,44 +74,165 @@ impl History {
     }
 }
 
-/// This is used to track the `Source` of a `Track` played by the bot.
-#[derive(Clone, Debug)]
-pub enum TrackUserData {
-    Youtube {
-        title: String,
-        url: String,
-    },
-    Attachment {
-        title: String,
-        attachment_url: String,
-    },
-    HttpStream {
-        url: String,
-    },
+/// The kind of source a track comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceKind {
+    Youtube,
+    Attachment,
+    HttpStream,
+}
+
+impl SourceKind {
+    const ALL: [SourceKind; 3] = [
+        SourceKind::Youtube,
+        SourceKind::Attachment,
+        SourceKind::HttpStream,
+    ];
+
+    /// Find the kind of source with `name`, e.g. as written to the play log.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Get a short key of the kind of source, without spaces or colons.
+    pub fn key(self) -> &'static str {
+        match self {
+            SourceKind::Youtube => "youtube",
+            SourceKind::Attachment => "file",
+            SourceKind::HttpStream => "stream",
+        }
+    }
+
+    /// Find the kind of source with `key`.
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.key() == key)
+    }
+
+    /// Get a short name of the kind of source.
+    pub fn name(self) -> &'static str {
+        match self {
+            SourceKind::Youtube => "YouTube",
+            SourceKind::Attachment => "file attachment",
+            SourceKind::HttpStream => "http stream",
+        }
+    }
+}
+
+/// This is used to track the `Source` of a `Track` played by the bot, along with everything known
+/// about the track.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackUserData {
+    pub kind: SourceKind,
+    pub title: String,
+    /// Where the track is loaded from.
+    pub url: String,
+    pub requester: Option<UserId>,
+    /// UNIX timestamp, in seconds, of when the track was added to the queue.
+    pub enqueued_at: u64,
+    pub duration: Option<Duration>,
+    pub artist: Option<String>,
+    /// URL of a thumbnail image.
+    pub thumbnail: Option<String>,
+    /// The channel or uploader which published the track.
+    pub uploader: Option<String>,
 }
 
 impl TrackUserData {
+    /// Create the data of a track which is being added to the queue right now.
+    ///
+    /// Everything beside the required fields starts out unknown.
+    pub fn new(kind: SourceKind, title: String, url: String, requester: Option<UserId>) -> Self {
+        Self {
+            kind,
+            title,
+            url,
+            requester,
+            enqueued_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration: None,
+            artist: None,
+            thumbnail: None,
+            uploader: None,
+        }
+    }
+
     /// Get the track title.
     pub fn title(&self) -> String {
-        match self {
-            TrackUserData::Youtube { title, url: _ } => title.clone(),
-            TrackUserData::Attachment {
-                title,
-                attachment_url: _,
-            } => title.clone(),
-            TrackUserData::HttpStream { url } => format!("HTTP stream: {url}"),
-        }
+        self.title.clone()
     }
 
     /// Get the source URL of the track.
     pub fn url(&self) -> String {
-        match self {
-            TrackUserData::Youtube { title: _, url } => url.clone(),
-            TrackUserData::Attachment {
-                title: _,
-                attachment_url,
-            } => attachment_url.clone(),
-            TrackUserData::HttpStream { url } => url.clone(),
+        self.url.clone()
+    }
+
+    /// Get the user who requested the track, if known.
+    pub fn requester(&self) -> Option<UserId> {
+        self.requester
+    }
+
+    /// Get a short name of the kind of source the track comes from.
+    pub fn source(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    /// Describe the track in a single line of markdown, e.g.
+    /// `[title](url) by artist (3:33, from YouTube, requested by @user)`.
+    pub fn describe(&self) -> String {
+        let mut details = vec![];
+        if let Some(duration) = self.duration {
+            details.push(crate::utils::format_duration(duration));
+        }
+        details.push(format!("from {}", self.source()));
+        if let Some(requester) = self.requester {
+            details.push(format!("requested by <@{requester}>"));
         }
+
+        let by = self
+            .artist
+            .as_ref()
+            .or(self.uploader.as_ref())
+            .map(|artist| format!(" by {artist}"))
+            .unwrap_or_default();
+
+        format!(
+            "[{}]({}){by} ({})",
+            self.title,
+            self.url,
+            details.join(", ")
+        )
+    }
+
+    /// Create a new, not yet started, input for the track from its source URL.
+    pub fn input(&self, client: reqwest::Client) -> Input {
+        match self.kind {
+            SourceKind::Youtube => YoutubeDl::new(client, self.url.clone()).into(),
+            SourceKind::Attachment | SourceKind::HttpStream => {
+                HttpRequest::new(client, self.url.clone()).into()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut track = TrackUserData::new(
+            SourceKind::HttpStream,
+            "Radio".into(),
+            "https://radio".into(),
+            Some(UserId::new(3)),
+        );
+        track.duration = Some(Duration::from_secs(90));
+        let json = serde_json::to_string(&track).unwrap();
+        let parsed: TrackUserData = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.requester, track.requester);
+        assert_eq!(parsed.duration, track.duration);
+        assert_eq!(parsed.enqueued_at, track.enqueued_at);
     }
 }
@@ -0,0 +1,193 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use serenity::all::{Cache, ChannelId, CreateEmbed, CreateMessage, GuildId, Http};
+use songbird::Songbird;
+use tokio::task::AbortHandle;
+
+use crate::{config::Config, queue::TrackQueue, settings::Settings};
+
+/// Why the bot leaves a voice channel on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// Nobody but bots is left in the voice channel.
+    Alone,
+    /// Nothing has been playing for a while.
+    Idle,
+}
+
+impl Reason {
+    fn message(self, timeout: Duration) -> String {
+        let minutes = timeout.as_secs().div_ceil(60);
+        match self {
+            Reason::Alone => {
+                format!("I left the voice channel, nobody listened for {minutes} minute(s).")
+            }
+            Reason::Idle => {
+                format!("I left the voice channel, nothing played for {minutes} minute(s).")
+            }
+        }
+    }
+}
+
+/// Makes the bot leave voice channels in which it is alone or idle for too long.
+///
+/// Every guild has a timer for each `Reason`, which is started by the voice state updates and
+/// track events which make the bot alone or idle. Once it runs out, the reason is checked again
+/// before leaving, so timers which are not cancelled in time do no harm.
+#[derive(Clone)]
+pub struct Inactivity {
+    http: Arc<Http>,
+    cache: Arc<Cache>,
+    songbird: Arc<Songbird>,
+    qs: Arc<Mutex<HashMap<GuildId, TrackQueue>>>,
+    settings: Settings,
+    alone_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    timers: Arc<Mutex<HashMap<(GuildId, Reason), AbortHandle>>>,
+}
+
+impl Inactivity {
+    pub fn new(
+        http: Arc<Http>,
+        cache: Arc<Cache>,
+        songbird: Arc<Songbird>,
+        qs: Arc<Mutex<HashMap<GuildId, TrackQueue>>>,
+        settings: Settings,
+        config: &Config,
+    ) -> Self {
+        Self {
+            http,
+            cache,
+            songbird,
+            qs,
+            settings,
+            alone_timeout: config.alone_timeout,
+            idle_timeout: config.idle_timeout,
+            timers: Arc::default(),
+        }
+    }
+
+    fn timeout(&self, reason: Reason) -> Option<Duration> {
+        match reason {
+            Reason::Alone => self.alone_timeout,
+            Reason::Idle => self.idle_timeout,
+        }
+    }
+
+    /// Start the timer for `reason` in `guild_id`, unless it is already running.
+    pub fn start(&self, guild_id: GuildId, reason: Reason) {
+        self.schedule(guild_id, reason, false);
+    }
+
+    /// Start the timer for `reason` in `guild_id` from the beginning.
+    pub fn restart(&self, guild_id: GuildId, reason: Reason) {
+        self.schedule(guild_id, reason, true);
+    }
+
+    fn schedule(&self, guild_id: GuildId, reason: Reason, restart: bool) {
+        let Some(timeout) = self.timeout(reason) else {
+            return;
+        };
+
+        let mut timers = self.timers.lock();
+        if let Some(timer) = timers.get(&(guild_id, reason))
+            && !timer.is_finished()
+        {
+            if !restart {
+                return;
+            }
+            timer.abort();
+        }
+
+        let inactivity = self.clone();
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+
+            // A restarted timer replaced this one in the meantime.
+            let id = tokio::task::id();
+            {
+                let mut timers = inactivity.timers.lock();
+                if timers.get(&(guild_id, reason)).map(AbortHandle::id) != Some(id) {
+                    return;
+                }
+                timers.remove(&(guild_id, reason));
+            }
+
+            inactivity.expire(guild_id, reason, timeout).await;
+        });
+        timers.insert((guild_id, reason), timer.abort_handle());
+    }
+
+    /// Stop the timer for `reason` in `guild_id`, if it is running.
+    pub fn cancel(&self, guild_id: GuildId, reason: Reason) {
+        if let Some(timer) = self.timers.lock().remove(&(guild_id, reason)) {
+            timer.abort();
+        }
+    }
+
+    /// Check whether the bot is alone in its voice channel in `guild_id`.
+    ///
+    /// `None` if the bot is not in a voice channel there.
+    pub fn alone(&self, guild_id: GuildId) -> Option<bool> {
+        let bot_id = self.cache.current_user().id;
+        let guild = self.cache.guild(guild_id)?;
+        guild.voice_states.get(&bot_id)?.channel_id?;
+
+        Some(crate::vote::listeners(&guild, bot_id).is_empty())
+    }
+
+    /// Start or cancel the timer for being alone, after someone joined or left a voice channel.
+    pub fn voice_state_changed(&self, guild_id: GuildId) {
+        match self.alone(guild_id) {
+            Some(true) => self.start(guild_id, Reason::Alone),
+            _ => self.cancel(guild_id, Reason::Alone),
+        }
+    }
+
+    /// Is the bot idle in `guild_id`, i.e. there is nothing to play?
+    fn idle(&self, guild_id: GuildId) -> bool {
+        let queue = self.qs.lock().get(&guild_id).cloned();
+        queue.is_none_or(|queue| queue.current().is_none())
+    }
+
+    /// Leave the voice channel of `guild_id`, if the bot is still alone or idle there.
+    async fn expire(&self, guild_id: GuildId, reason: Reason, timeout: Duration) {
+        if self.settings.get(guild_id).stay_connected || self.songbird.get(guild_id).is_none() {
+            return;
+        }
+        let still = match reason {
+            Reason::Alone => self.alone(guild_id) == Some(true),
+            Reason::Idle => self.idle(guild_id),
+        };
+        if !still {
+            return;
+        }
+
+        // The reason is announced where the now playing panel was, if nowhere else.
+        let queue = self.qs.lock().get(&guild_id).cloned();
+        let channel: Option<ChannelId> = self
+            .settings
+            .get(guild_id)
+            .announce_channel
+            .or_else(|| queue?.panel().map(|(channel, _)| channel));
+
+        // Leaving makes Discord send a voice state update, which cleans up the queue.
+        if let Err(e) = self.songbird.remove(guild_id).await {
+            println!("could not leave the voice channel of {guild_id}: {e}");
+        }
+        println!("left the voice channel of {guild_id}: {reason:?}");
+
+        if let Some(channel) = channel {
+            let embed = CreateEmbed::new()
+                .title("Info")
+                .description(reason.message(timeout));
+            if let Err(e) = channel
+                .send_message(&self.http, CreateMessage::new().embed(embed))
+                .await
+            {
+                println!("could not announce leaving the voice channel: {e}");
+            }
+        }
+    }
+}
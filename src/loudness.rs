@@ -0,0 +1,298 @@
+use std::{collections::VecDeque, f64::consts::PI, time::Duration};
+
+use songbird::input::{
+    Input, LiveInput, Parsed,
+    codecs::{get_codec_registry, get_probe},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    errors::Error as SymphoniaError,
+    meta::{MetadataRevision, StandardTagKey},
+};
+
+use crate::filters::Biquad;
+
+/// How much of a track is decoded to measure its loudness.
+const ANALYSIS_LENGTH: Duration = Duration::from_secs(30);
+
+/// The loudness ReplayGain 2.0 adjusts tracks to, in LUFS.
+const REPLAY_GAIN_REFERENCE: f64 = -18.0;
+
+/// Blocks quieter than this are silence and ignored, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Blocks this much quieter than the rest are ignored, in LU.
+const RELATIVE_GATE: f64 = -10.0;
+
+/// The largest gain applied to quiet tracks, louder tracks can be turned down without limit.
+const MAX_BOOST_DB: f64 = 10.0;
+
+/// The K-weighting filter of ITU-R BS.1770, for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = f64::from(sample_rate);
+
+    // A high shelf, modelling the acoustic effect of the head.
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // A high pass, the RLB weighting curve.
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [shelf, high_pass]
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Measures the integrated loudness of audio, like EBU R128.
+///
+/// The audio is K-weighted and cut into blocks of 400ms, overlapping by 75%. The loudness is
+/// the average of all blocks which are neither silent nor much quieter than the rest.
+#[derive(Clone, Debug)]
+pub struct Meter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    /// Frames in a 100ms step, four of them make up a block.
+    step_length: usize,
+    /// Sum of the squared samples of the current step.
+    step_energy: f64,
+    step_frames: usize,
+    /// Energy of the last steps, to build overlapping blocks from.
+    steps: VecDeque<f64>,
+    /// The mean power of every block so far.
+    blocks: Vec<f64>,
+    frames: usize,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            step_length: (sample_rate as usize / 10).max(1),
+            step_energy: 0.0,
+            step_frames: 0,
+            steps: VecDeque::with_capacity(4),
+            blocks: vec![],
+            frames: 0,
+        }
+    }
+
+    /// Add interleaved samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, filters) in frame.iter().zip(&mut self.filters) {
+                let weighted = filters
+                    .iter_mut()
+                    .fold(f64::from(*sample), |x, filter| filter.process(x));
+                self.step_energy += weighted * weighted;
+            }
+
+            self.frames += 1;
+            self.step_frames += 1;
+            if self.step_frames == self.step_length {
+                if self.steps.len() == 4 {
+                    self.steps.pop_front();
+                }
+                self.steps.push_back(self.step_energy);
+                if self.steps.len() == 4 {
+                    let energy = self.steps.iter().sum::<f64>();
+                    self.blocks.push(energy / (4 * self.step_length) as f64);
+                }
+
+                self.step_energy = 0.0;
+                self.step_frames = 0;
+            }
+        }
+    }
+
+    /// How much audio was measured so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Get the integrated loudness in LUFS, unless everything was silent.
+    pub fn integrated(&self) -> Option<f64> {
+        let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+            let (sum, count) = blocks.fold((0.0, 0), |(sum, count), p| (sum + p, count + 1));
+            (count > 0).then(|| sum / f64::from(count))
+        };
+
+        let audible = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&power| power_to_lufs(power) > ABSOLUTE_GATE);
+        let relative_gate = power_to_lufs(mean(&mut audible.clone())?) + RELATIVE_GATE;
+        let loud = mean(&mut audible.filter(|&power| power_to_lufs(power) > relative_gate))?;
+
+        Some(power_to_lufs(loud))
+    }
+}
+
+/// Parse a ReplayGain tag, e.g. `-6.52 dB`.
+pub fn parse_replay_gain(tag: &str) -> Option<f64> {
+    let tag = tag.trim().replace('\u{2212}', "-");
+    let number = tag
+        .strip_suffix("dB")
+        .or_else(|| tag.strip_suffix("db"))
+        .unwrap_or(&tag);
+
+    number
+        .trim()
+        .parse()
+        .ok()
+        .filter(|gain: &f64| gain.is_finite())
+}
+
+/// How many decibels a track of `loudness` has to be changed by to reach `target`, both in LUFS.
+pub fn gain_db(loudness: f64, target: f64) -> f64 {
+    (target - loudness).min(MAX_BOOST_DB)
+}
+
+/// Turn decibels into a factor for the volume.
+pub fn db_to_volume(db: f64) -> f32 {
+    10f64.powf(db / 20.0) as f32
+}
+
+/// Measure the loudness of a track in LUFS, from its ReplayGain tag or its first seconds.
+pub async fn measure(input: Input) -> Option<f64> {
+    let input = match input
+        .make_playable_async(get_codec_registry(), get_probe())
+        .await
+    {
+        Ok(input) => input,
+        Err(e) => {
+            println!("could not open a track to measure its loudness: {e}");
+            return None;
+        }
+    };
+    let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+        return None;
+    };
+
+    // Reading and decoding uses blocking IO.
+    tokio::task::spawn_blocking(move || measure_parsed(parsed))
+        .await
+        .ok()?
+}
+
+fn replay_gain(parsed: &mut Parsed) -> Option<f64> {
+    let find = |revision: &MetadataRevision| {
+        revision
+            .tags()
+            .iter()
+            .filter(|tag| tag.std_key == Some(StandardTagKey::ReplayGainTrackGain))
+            .find_map(|tag| parse_replay_gain(&tag.value.to_string()))
+    };
+
+    let outside = parsed
+        .meta
+        .get()
+        .as_ref()
+        .and_then(|meta| meta.current().and_then(find));
+    outside.or_else(|| parsed.format.metadata().current().and_then(find))
+}
+
+fn measure_parsed(mut parsed: Parsed) -> Option<f64> {
+    if let Some(gain) = replay_gain(&mut parsed) {
+        return Some(REPLAY_GAIN_REFERENCE - gain);
+    }
+
+    let mut meter: Option<Meter> = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    while let Ok(packet) = parsed.format.next_packet() {
+        if packet.track_id() != parsed.track_id {
+            continue;
+        }
+
+        let decoded = match parsed.decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A broken packet can be skipped, anything else ends the stream.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+        let spec = *decoded.spec();
+        let meter = meter.get_or_insert_with(|| Meter::new(spec.rate, spec.channels.count()));
+
+        if buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.capacity() < decoded.capacity())
+        {
+            buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buffer = buffer.as_mut().expect("Was just created.");
+        buffer.copy_interleaved_ref(decoded);
+        meter.push(buffer.samples());
+
+        if meter.frames() as u64 >= u64::from(spec.rate) * ANALYSIS_LENGTH.as_secs() {
+            break;
+        }
+    }
+
+    meter?.integrated()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f32, seconds: usize) -> Vec<f32> {
+        (0..48000 * seconds)
+            .flat_map(|i| {
+                let sample =
+                    amplitude * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sine_loudness() {
+        // A 1 kHz sine at -20 dBFS in both channels is -20 LUFS.
+        let mut meter = Meter::new(48000, 2);
+        meter.push(&sine(0.1, 5));
+        let loudness = meter.integrated().unwrap();
+        assert!((loudness + 20.0).abs() < 0.1, "{loudness}");
+
+        // Silence is gated away, only the few blocks overlapping the end of the sine count.
+        meter.push(&vec![0.0; 48000 * 2 * 5]);
+        assert!((meter.integrated().unwrap() - loudness).abs() < 0.5);
+
+        let mut silent = Meter::new(48000, 2);
+        silent.push(&vec![0.0; 48000 * 2]);
+        assert_eq!(silent.integrated(), None);
+    }
+
+    #[test]
+    fn gains() {
+        assert_eq!(parse_replay_gain("-6.52 dB"), Some(-6.52));
+        assert_eq!(parse_replay_gain("+1.5dB"), Some(1.5));
+        assert_eq!(parse_replay_gain("\u{2212}3 dB"), Some(-3.0));
+        assert_eq!(parse_replay_gain("loud"), None);
+
+        assert_eq!(gain_db(-20.0, -14.0), 6.0);
+        assert_eq!(gain_db(-8.0, -14.0), -6.0);
+        assert_eq!(gain_db(-40.0, -14.0), MAX_BOOST_DB);
+        assert!((db_to_volume(-6.0) - 0.501).abs() < 0.001);
+    }
+}
@@ -5,44 +5,101 @@ use std::{collections::HashMap, sync::Arc};
 use parking_lot::Mutex;
 use poise::{Framework, FrameworkOptions, PrefixFrameworkOptions};
 use serenity::model::{gateway::GatewayIntents, id::GuildId};
-use songbird::{Config, SerenityInit};
+use songbird::SerenityInit;
 
+mod autocomplete;
 mod callbacks;
 mod commands;
+mod config;
+mod error;
+mod favourites;
+mod filters;
 mod handlers;
 mod history;
+mod inactivity;
+mod loudness;
+mod metadata;
+mod panel;
+mod permissions;
+mod persist;
+mod playlog;
 mod queue;
+mod settings;
 mod utils;
-
-use crate::queue::TrackQueue;
-
-// For owner only commands.
-pub const OWNER_ID: u64 = 272795263414829057;
+mod vote;
+
+use crate::{
+    autocomplete::SearchCache,
+    config::Config,
+    favourites::Favourites,
+    inactivity::Inactivity,
+    persist::{QueueSnapshot, Store},
+    playlog::PlayLog,
+    queue::TrackQueue,
+    settings::Settings,
+};
 
 // Useful aliases.
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type Error = error::ScumboError;
 pub type Result_<T> = Result<T, Error>;
 pub type Context<'a> = poise::Context<'a, State, Error>;
 
 // Bot state goes here.
 pub struct State {
+    config: Config,
     client: reqwest::Client,
     qs: Arc<Mutex<HashMap<GuildId, TrackQueue>>>,
+    /// Queues loaded from disk, which are restored when the bot joins the guild's voice channel.
+    restored: Mutex<HashMap<GuildId, QueueSnapshot>>,
+    /// Where queues are saved.
+    store: Store,
+    /// Log of every played track, enabled by configuring `history_db`.
+    play_log: Option<PlayLog>,
+    /// Settings of each guild.
+    settings: Settings,
+    /// Tracks saved by each user.
+    favourites: Favourites,
+    /// Previous search results, used for autocompletion.
+    search_cache: SearchCache,
+    /// Leaves voice channels which nobody uses.
+    inactivity: Inactivity,
 }
 
 #[tokio::main]
 async fn main() -> Result_<()> {
     // TODO: Possibly setup logging first.
 
-    // Parse `.env` file.
-    dotenv::dotenv().expect("cannot load env");
-
-    let token = dotenv::var("DISCORD_TOKEN")?;
-    let prefix = dotenv::var("BOT_PREFIX").unwrap_or("!".into());
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", config::USAGE);
+        return Ok(());
+    }
+
+    // Read the config file, the environment and the command line flags.
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    if config.owners.is_empty() {
+        println!("no owners configured, owner only commands can't be used");
+    }
+
+    let store = Store::new(&config.data_dir);
+    let favourites = Favourites::load(config.data_dir.join("favourites.json"))?;
+    let settings = Settings::load(config.data_dir.join("settings.json"))?;
+
+    // Load the queues saved when the bot last ran.
+    let restored = store.load_all()?;
+    let qs: Arc<Mutex<HashMap<GuildId, TrackQueue>>> = Default::default();
+    let play_log = config.history_db.as_ref().map(PlayLog::open).transpose()?;
 
     // Set all unprivileged intents.
     //
-    // Because we want to use prefixes, the `MESSAGE_CONTENT` intent is also necessary.
+    // All commands can be used as slash commands, but prefix commands are still supported, for
+    // which the `MESSAGE_CONTENT` intent is necessary.
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::GUILD_VOICE_STATES;
@@ -57,45 +114,134 @@ async fn main() -> Result_<()> {
                 crate::commands::help(),
                 crate::commands::join(),
                 crate::commands::leave(),
+                crate::commands::move_to(),
+                crate::commands::follow(),
                 crate::commands::play(),
                 crate::commands::queue(),
                 crate::commands::pause(),
+                crate::commands::nowplaying(),
+                crate::commands::seek(),
+                crate::commands::volume(),
+                crate::commands::filter(),
                 crate::commands::stop(),
+                crate::commands::skip(),
+                crate::commands::loop_mode(),
+                crate::commands::stats(),
+                crate::commands::back(),
+                crate::commands::replay(),
+                crate::commands::fav(),
+                crate::commands::config(),
+                crate::commands::register(),
             ],
             on_error: crate::callbacks::on_error,
-            owners: std::collections::HashSet::from([OWNER_ID.into()]),
+            event_handler: crate::callbacks::on_event,
+            owners: config.owners.iter().copied().collect(),
             prefix_options: PrefixFrameworkOptions {
-                prefix: Some(prefix),
+                // Every guild can have its own prefix, falling back to the global one.
+                dynamic_prefix: Some(|ctx| {
+                    Box::pin(async move {
+                        Ok(Some(match ctx.guild_id {
+                            Some(guild_id) => {
+                                ctx.data.settings.get(guild_id).prefix(&ctx.data.config)
+                            }
+                            None => ctx.data.config.prefix.clone(),
+                        }))
+                    })
+                }),
                 ..Default::default()
             },
             ..Default::default()
         })
         // Run the framework setup, initializing user data.
-        .setup(move |_, _, _| {
-            Box::pin(async move {
-                Ok(State {
-                    qs: Default::default(),
-                    client: reqwest::Client::new(),
+        .setup({
+            let (qs, config, store) = (qs.clone(), config.clone(), store.clone());
+            move |ctx, _, _| {
+                Box::pin(async move {
+                    let inactivity = Inactivity::new(
+                        ctx.http.clone(),
+                        ctx.cache.clone(),
+                        songbird::get(ctx)
+                            .await
+                            .expect("Registered with the client."),
+                        qs.clone(),
+                        settings.clone(),
+                        &config,
+                    );
+
+                    Ok(State {
+                        config,
+                        qs,
+                        client: reqwest::Client::new(),
+                        restored: Mutex::new(restored),
+                        store,
+                        play_log,
+                        settings,
+                        favourites,
+                        search_cache: SearchCache::default(),
+                        inactivity,
+                    })
                 })
-            })
+            }
         })
         .build();
 
     // Create a `songbird` configuration.
-    let songbird_config = Config::default()
-        .preallocated_tracks(16)
-        .use_softclip(false)
-        .driver_timeout(Some(std::time::Duration::from_secs(30)));
+    let songbird_config = songbird::Config::default()
+        .preallocated_tracks(config.preallocated_tracks)
+        .use_softclip(config.softclip)
+        .driver_timeout(config.driver_timeout);
 
     // Setup the discord client.
-    let mut client = serenity::Client::builder(token, intents)
+    let mut client = serenity::Client::builder(&config.token, intents)
         .framework(framework)
         .register_songbird_from_config(songbird_config)
         .await
         .expect("client should have been correctly created");
 
+    // Periodically save all queues to disk.
+    tokio::spawn({
+        let (store, qs) = (store.clone(), qs.clone());
+        async move {
+            let mut interval = tokio::time::interval(persist::SNAPSHOT_INTERVAL);
+            loop {
+                interval.tick().await;
+                store.save_all(&qs).await;
+            }
+        }
+    });
+
+    // Save all queues once more before shutting down, on Ctrl-C or when the service manager
+    // stops the bot.
+    tokio::spawn({
+        let shard_manager = client.shard_manager.clone();
+        async move {
+            if shutdown_signal().await.is_ok() {
+                store.save_all(&qs).await;
+                shard_manager.shutdown_all().await;
+            }
+        }
+    });
+
     // Run the bot.
     client.start().await?;
 
     Ok(())
 }
+
+/// Wait for Ctrl-C, or for SIGTERM on Unix.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
@@ -0,0 +1,224 @@
+use std::{io::Cursor, time::Duration};
+
+use reqwest::header::{CONTENT_DISPOSITION, HeaderMap};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+
+/// How much of an attachment is downloaded to look for its tags, they are usually at the start.
+const PROBE_LIMIT: usize = 512 * 1024;
+
+/// How long to wait for the headers of an HTTP stream.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Metadata found in the tags of an audio file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// Metadata found in the headers of an HTTP stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// The name of the station, or the file name.
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Probe the beginning of an audio file for its tags and duration.
+///
+/// `extension` helps symphonia to guess the format. Anything which can't be read is left out.
+pub fn probe(bytes: Vec<u8>, extension: Option<&str>) -> Tags {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let Ok(mut probed) = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) else {
+        return Tags::default();
+    };
+
+    let mut tags = Tags::default();
+    let mut read = |revision: &MetadataRevision| {
+        for tag in revision.tags() {
+            let slot = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut tags.title,
+                Some(StandardTagKey::Artist) => &mut tags.artist,
+                _ => continue,
+            };
+            slot.get_or_insert_with(|| tag.value.to_string());
+        }
+    };
+
+    // Tags can be outside of the container (e.g. ID3) or part of it (e.g. Vorbis comments).
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        read(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        read(revision);
+    }
+
+    tags.duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let time = params.time_base?.calc_time(params.n_frames?);
+        Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+    });
+
+    tags
+}
+
+/// Download the beginning of an attachment and probe it for its tags.
+pub async fn attachment(client: &reqwest::Client, url: &str, extension: Option<&str>) -> Tags {
+    let download = async {
+        let mut response = client.get(url).send().await?.error_for_status()?;
+        let mut bytes = vec![];
+        while bytes.len() < PROBE_LIMIT
+            && let Some(chunk) = response.chunk().await?
+        {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok::<_, reqwest::Error>(bytes)
+    };
+
+    match download.await {
+        Ok(bytes) => tokio::task::spawn_blocking({
+            let extension = extension.map(str::to_owned);
+            move || probe(bytes, extension.as_deref())
+        })
+        .await
+        .unwrap_or_default(),
+        Err(e) => {
+            println!("could not download attachment {url} for probing: {e}");
+            Tags::default()
+        }
+    }
+}
+
+/// Read the station name and description from ICY headers, or the file name from the
+/// `Content-Disposition` header.
+pub fn stream_info(headers: &HeaderMap) -> StreamInfo {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_owned)
+    };
+
+    let file_name = header(CONTENT_DISPOSITION.as_str()).and_then(|disposition| {
+        disposition.split(';').find_map(|part| {
+            let part = part.trim();
+            if let Some(encoded) = part.strip_prefix("filename*=") {
+                // RFC 5987, e.g. `UTF-8''na%C3%AFve.mp3`.
+                let (_, encoded) = encoded.split_once("''")?;
+                return percent_decode(encoded);
+            }
+            let name = part.strip_prefix("filename=")?.trim_matches('"');
+            Some(name.to_owned())
+        })
+    });
+
+    StreamInfo {
+        title: header("icy-name").or(file_name),
+        description: header("icy-description"),
+    }
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut chars = input.bytes();
+
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex = [chars.next()?, chars.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Request an HTTP stream just to read its headers.
+pub async fn stream(client: &reqwest::Client, url: &str) -> StreamInfo {
+    let request = client
+        .get(url)
+        .header("Icy-MetaData", "1")
+        .timeout(STREAM_TIMEOUT)
+        .send();
+
+    match request.await {
+        // The body is never read, dropping the response closes the connection.
+        Ok(response) => stream_info(response.headers()),
+        Err(e) => {
+            println!("could not request stream {url} for its headers: {e}");
+            StreamInfo::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_duration() {
+        // Two seconds of silent 8 kHz, 8 bit mono audio.
+        let samples = 2 * 8000;
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples as u32).to_le_bytes());
+        wav.resize(wav.len() + samples, 128);
+
+        let tags = probe(wav, Some("wav"));
+        assert_eq!(tags.duration, Some(Duration::from_secs(2)));
+        assert_eq!(tags.title, None);
+
+        assert_eq!(probe(b"not audio".to_vec(), None), Tags::default());
+    }
+
+    #[test]
+    fn headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_DISPOSITION,
+            "attachment; filename*=UTF-8''na%C3%AFve.mp3"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(stream_info(&headers).title.as_deref(), Some("naïve.mp3"));
+
+        headers.insert("icy-name", "Radio Scumbo".parse().unwrap());
+        headers.insert("icy-description", "All day".parse().unwrap());
+        assert_eq!(
+            stream_info(&headers),
+            StreamInfo {
+                title: Some("Radio Scumbo".into()),
+                description: Some("All day".into()),
+            }
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use std::time::Duration;
+
+use poise::ChoiceParameter;
+use serenity::all::{
+    ButtonStyle, ChannelId, ComponentInteraction, Context as SerenityContext, CreateActionRow,
+    CreateButton, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CreateMessage, EditMessage, GuildId, Http,
+};
+use songbird::tracks::PlayMode;
+
+use crate::{
+    Result_, State,
+    queue::{LoopMode, TrackQueue},
+};
+
+/// How often the progress bar of the panel is updated while a track plays.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Custom IDs of the panel buttons start with this, followed by the guild and the action.
+const PREFIX: &str = "panel";
+
+/// How many characters the progress bar is wide.
+const BAR_WIDTH: usize = 20;
+
+/// What a button of the panel does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    PauseResume,
+    Skip,
+    Stop,
+    Shuffle,
+    Loop,
+}
+
+impl Action {
+    const ALL: [Action; 5] = [
+        Action::PauseResume,
+        Action::Skip,
+        Action::Stop,
+        Action::Shuffle,
+        Action::Loop,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::PauseResume => "pause",
+            Action::Skip => "skip",
+            Action::Stop => "stop",
+            Action::Shuffle => "shuffle",
+            Action::Loop => "loop",
+        }
+    }
+
+    /// The custom ID of the button doing this in `guild_id`.
+    ///
+    /// It contains the guild, so the buttons of a panel keep working after a restart.
+    pub fn custom_id(self, guild_id: GuildId) -> String {
+        format!("{PREFIX}:{guild_id}:{}", self.name())
+    }
+
+    /// Parse the custom ID of a panel button.
+    pub fn parse(custom_id: &str) -> Option<(GuildId, Action)> {
+        let mut parts = custom_id.split(':');
+        if parts.next()? != PREFIX {
+            return None;
+        }
+        let guild_id = parts.next()?.parse::<u64>().ok().filter(|&id| id != 0)?;
+        let name = parts.next()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        let action = Action::ALL
+            .into_iter()
+            .find(|action| action.name() == name)?;
+        Some((GuildId::new(guild_id), action))
+    }
+}
+
+/// Show how far into a track playback is, e.g. `▬▬▬🔘▬▬▬ 1:02 / 3:33`.
+///
+/// Tracks of unknown length, like radio streams, only show the position.
+pub fn progress_bar(position: Duration, duration: Option<Duration>) -> String {
+    let position_text = crate::utils::format_duration(position);
+    let Some(duration) = duration.filter(|duration| !duration.is_zero()) else {
+        return format!("🔴 {position_text} (live)");
+    };
+
+    let progress = (position.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0);
+    let filled = (progress * BAR_WIDTH as f64).round() as usize;
+    format!(
+        "{}🔘{} {position_text} / {}",
+        "▬".repeat(filled),
+        "▬".repeat(BAR_WIDTH - filled),
+        crate::utils::format_duration(duration)
+    )
+}
+
+/// Turn a volume, where `1.0` is the unchanged volume, into percent.
+pub fn percent(volume: f32) -> u16 {
+    (volume * 100.0).round() as u16
+}
+
+/// The embed showing the current track and how far it has played, if anything is playing.
+pub async fn now_playing(q: &TrackQueue) -> Option<CreateEmbed> {
+    embed(q).await.map(|(embed, _)| embed)
+}
+
+/// Build the embed of the current track, with whether it is paused.
+async fn embed(q: &TrackQueue) -> Option<(CreateEmbed, bool)> {
+    let queued = q.current_queue();
+    let handle = q.current()?;
+    let data = queued.first()?;
+
+    let info = handle.get_info().await.ok();
+    let paused = info
+        .as_ref()
+        .is_some_and(|info| info.playing == PlayMode::Pause);
+    let position = info.map(|info| info.position).unwrap_or_default();
+
+    let by = data
+        .artist
+        .as_ref()
+        .or(data.uploader.as_ref())
+        .map(|artist| format!(" by {artist}"))
+        .unwrap_or_default();
+    let requester = match data.requester {
+        Some(requester) => format!("<@{requester}>"),
+        None => "unknown".into(),
+    };
+
+    let volume = match q.track_volume() {
+        Some(volume) => format!("{}% (this track)", percent(volume)),
+        None => format!("{}%", percent(q.volume())),
+    };
+
+    let mut embed = CreateEmbed::new()
+        .title(if paused { "Paused" } else { "Now playing" })
+        .description(format!(
+            "[{}]({}){by}\n\n{}",
+            data.title,
+            data.url,
+            progress_bar(position, q.current_duration())
+        ))
+        .field("Requested by", requester, true)
+        .field("Up next", format!("{} track(s)", queued.len() - 1), true)
+        .field("Loop", format!("`{}`", q.loop_mode().name()), true)
+        .field("Volume", volume, true);
+    if q.normalising() {
+        let gain = match q.track_gain() {
+            Some(gain) => format!("{gain:+.1} dB"),
+            None => "measuring…".into(),
+        };
+        embed = embed.field("Normalisation", gain, true);
+    }
+    let filters = q.filters().list();
+    if !filters.is_empty() {
+        let names: Vec<_> = filters.iter().map(|filter| filter.name()).collect();
+        embed = embed.field("Filters", names.join(", "), true);
+    }
+    if let Some(thumbnail) = &data.thumbnail {
+        embed = embed.thumbnail(thumbnail);
+    }
+
+    Some((embed, paused))
+}
+
+/// Render the panel for the current state of the queue.
+///
+/// While nothing is playing the panel says so, and its buttons are disabled.
+async fn render(q: &TrackQueue) -> (CreateEmbed, Vec<CreateActionRow>) {
+    let guild_id = q.guild_id();
+    let (embed, paused) = match embed(q).await {
+        Some((embed, paused)) => (embed, Some(paused)),
+        None => {
+            let embed = CreateEmbed::new()
+                .title("Nothing is playing")
+                .description("Add tracks with `play`.");
+            (embed, None)
+        }
+    };
+    q.set_panel_idle(paused.is_none());
+    let loop_mode = q.loop_mode();
+
+    let button = |action: Action, label: &str| {
+        CreateButton::new(action.custom_id(guild_id))
+            .label(label)
+            .style(ButtonStyle::Secondary)
+            .disabled(paused.is_none())
+    };
+    let buttons = vec![
+        button(
+            Action::PauseResume,
+            if paused == Some(true) {
+                "Resume"
+            } else {
+                "Pause"
+            },
+        )
+        .style(ButtonStyle::Primary),
+        button(Action::Skip, "Skip"),
+        button(Action::Stop, "Stop").style(ButtonStyle::Danger),
+        button(Action::Shuffle, "Shuffle"),
+        button(Action::Loop, &format!("Loop: {}", loop_mode.name())),
+    ];
+
+    (embed, vec![CreateActionRow::Buttons(buttons)])
+}
+
+/// Show the panel in `channel_id`, editing the existing panel if it is already there.
+///
+/// A panel in another channel is replaced by a new one.
+pub async fn show(http: &Http, q: &TrackQueue, channel_id: ChannelId) {
+    let (embed, components) = render(q).await;
+
+    match q.panel() {
+        Some((channel, message)) if channel == channel_id => {
+            let edit = EditMessage::new()
+                .embed(embed.clone())
+                .components(components.clone());
+            if channel.edit_message(http, message, edit).await.is_ok() {
+                return;
+            }
+        }
+        Some((channel, message)) => drop(channel.delete_message(http, message).await),
+        None => {}
+    }
+
+    // The panel was never sent, was deleted or is in another channel.
+    match channel_id
+        .send_message(
+            http,
+            CreateMessage::new().embed(embed).components(components),
+        )
+        .await
+    {
+        Ok(sent) => q.set_panel(Some((sent.channel_id, sent.id))),
+        Err(e) => println!("could not send the now playing panel: {e}"),
+    }
+}
+
+/// Update the existing panel, e.g. to move its progress bar along.
+///
+/// Once the queue has run out the panel is shown idle, and left alone until something plays.
+pub async fn refresh(http: &Http, q: &TrackQueue) {
+    let Some((channel, message)) = q.panel() else {
+        return;
+    };
+    if q.current().is_none() && q.panel_idle() {
+        return;
+    }
+
+    let (embed, components) = render(q).await;
+    let edit = EditMessage::new().embed(embed).components(components);
+    if let Err(e) = channel.edit_message(http, message, edit).await {
+        println!("could not refresh the now playing panel: {e}");
+    }
+}
+
+/// Handle a press of a panel button, with the same permissions as the matching commands.
+pub async fn press(
+    ctx: &SerenityContext,
+    press: &ComponentInteraction,
+    data: &State,
+) -> Result_<()> {
+    let Some((guild_id, action)) = Action::parse(&press.data.custom_id) else {
+        return Ok(());
+    };
+    if press.guild_id != Some(guild_id) {
+        return Ok(());
+    }
+
+    let respond = |content: &'static str| {
+        CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .ephemeral(true),
+        )
+    };
+
+    let Some(q) = data.qs.lock().get(&guild_id).cloned() else {
+        press
+            .create_response(ctx, respond("Not in a voice channel, good sir!"))
+            .await?;
+        return Ok(());
+    };
+
+    let is_dj = || {
+        let dj_role = data.settings.get(guild_id).dj_role;
+        press.member.as_ref().is_some_and(|member| {
+            crate::permissions::member_is_dj(
+                &ctx.cache,
+                guild_id,
+                dj_role,
+                member,
+                press.channel_id,
+                ctx.cache.current_user().id,
+            )
+        })
+    };
+
+    match action {
+        Action::PauseResume => {
+            let paused = match q.current() {
+                Some(handle) => handle
+                    .get_info()
+                    .await
+                    .is_ok_and(|info| info.playing == PlayMode::Pause),
+                None => false,
+            };
+            if paused {
+                q.resume()?;
+            } else {
+                q.pause()?;
+            }
+        }
+        Action::Skip => {
+            let own_track = q
+                .current_queue()
+                .first()
+                .is_some_and(|track| track.requester() == Some(press.user.id));
+            if !own_track && !is_dj() {
+                press
+                    .create_response(
+                        ctx,
+                        respond("Only DJs can skip other listeners' tracks, use `skip` to vote."),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            q.skip(1).await;
+        }
+        Action::Stop | Action::Shuffle => {
+            if !is_dj() {
+                press
+                    .create_response(
+                        ctx,
+                        respond(
+                            "Only DJs can do that, you need the DJ role or the Manage Server \
+                             permission.",
+                        ),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            if action == Action::Stop {
+                q.stop();
+            } else {
+                q.shuffle();
+            }
+        }
+        Action::Loop => q.set_loop_mode(match q.loop_mode() {
+            LoopMode::Off => LoopMode::Track,
+            LoopMode::Track => LoopMode::Queue,
+            LoopMode::Queue => LoopMode::Off,
+        }),
+    }
+
+    let (embed, components) = render(&q).await;
+    press
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(components),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_ids() {
+        let guild_id = GuildId::new(42);
+        for action in Action::ALL {
+            assert_eq!(
+                Action::parse(&action.custom_id(guild_id)),
+                Some((guild_id, action))
+            );
+        }
+
+        assert_eq!(Action::parse("panel:42:dance"), None);
+        assert_eq!(Action::parse("panel:0:skip"), None);
+        assert_eq!(Action::parse("panel:42:skip:extra"), None);
+        assert_eq!(Action::parse("123vote-skip"), None);
+    }
+
+    #[test]
+    fn progress() {
+        let bar = progress_bar(Duration::from_secs(30), Some(Duration::from_secs(120)));
+        assert_eq!(
+            bar,
+            format!("{}🔘{} 0:30 / 2:00", "▬".repeat(5), "▬".repeat(15))
+        );
+
+        let bar = progress_bar(Duration::from_secs(200), Some(Duration::from_secs(120)));
+        assert!(bar.starts_with(&format!("{}🔘 ", "▬".repeat(BAR_WIDTH))));
+
+        assert_eq!(
+            progress_bar(Duration::from_secs(61), None),
+            "🔴 1:01 (live)"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use serenity::all::{Cache, ChannelId, GuildId, Member, RoleId, UserId};
+
+use crate::{Context, Result_, error::ScumboError, history::TrackUserData};
+
+/// Can the author of the command change the whole queue?
+///
+/// Members with the DJ role, if the guild set one up, or the Manage Server permission are DJs, and
+/// so is anyone who is alone with the bot in its voice channel.
+pub async fn is_dj(ctx: Context<'_>) -> Result_<bool> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(false);
+    };
+    let dj_role = ctx.data().settings.get(guild_id).dj_role;
+
+    let member = ctx
+        .author_member()
+        .await
+        .ok_or(ScumboError::UnknownMember)?
+        .into_owned();
+
+    Ok(member_is_dj(
+        ctx.cache(),
+        guild_id,
+        dj_role,
+        &member,
+        ctx.channel_id(),
+        ctx.framework().bot_id,
+    ))
+}
+
+/// Is `member` a DJ, when using the bot from `channel_id`? See `is_dj`.
+pub fn member_is_dj(
+    cache: &Cache,
+    guild_id: GuildId,
+    dj_role: Option<RoleId>,
+    member: &Member,
+    channel_id: ChannelId,
+    bot_id: UserId,
+) -> bool {
+    if dj_role.is_some_and(|role| member.roles.contains(&role)) {
+        return true;
+    }
+
+    // Slash commands come with the permissions of the member, prefix commands need the cache.
+    let Some(guild) = cache.guild(guild_id) else {
+        return false;
+    };
+    let permissions = member.permissions.or_else(|| {
+        let channel = guild.channels.get(&channel_id)?;
+        Some(guild.user_permissions_in(channel, member))
+    });
+    if permissions.is_some_and(|p| p.manage_guild() || p.administrator()) {
+        return true;
+    }
+
+    let channel_of = |user| {
+        guild
+            .voice_states
+            .get(&user)
+            .and_then(|state| state.channel_id)
+    };
+    channel_of(bot_id).is_some_and(|channel| {
+        channel_of(member.user.id) == Some(channel)
+            && guild.voice_states.values().all(|state| {
+                state.channel_id != Some(channel)
+                    || state.user_id == bot_id
+                    || state.user_id == member.user.id
+            })
+    })
+}
+
+/// Can the author change all of `tracks`?
+///
+/// Everyone may change the tracks they requested themselves, only DJs may change the rest.
+pub async fn may_change(ctx: Context<'_>, tracks: &[Arc<TrackUserData>]) -> Result_<bool> {
+    let author = ctx.author().id;
+    if tracks.iter().all(|track| track.requester() == Some(author)) {
+        return Ok(true);
+    }
+
+    is_dj(ctx).await
+}
+
+/// Check for commands which change the whole queue, so only DJs may use them.
+pub async fn dj(ctx: Context<'_>) -> Result_<bool> {
+    if is_dj(ctx).await? {
+        return Ok(true);
+    }
+
+    Err(ScumboError::Permission(
+        "Only DJs can do that, you need the DJ role or the Manage Server permission.".into(),
+    ))
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, MessageId};
+
+use crate::{
+    Result_,
+    filters::Filter,
+    history::TrackUserData,
+    queue::{LoopMode, TrackQueue},
+};
+
+/// How often all queues are saved to disk.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Write `bytes` to `path`, replacing the file only once all of it is written.
+///
+/// The bytes go to a temporary file next to `path` first, so a crash while writing can't leave a
+/// truncated file behind.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// A single queued track, as it is saved on disk.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackSnapshot {
+    pub data: TrackUserData,
+    /// Volume override of the track, if it has one.
+    #[serde(default)]
+    pub volume: Option<f32>,
+    /// Measured loudness of the track in LUFS, if it is known.
+    #[serde(default)]
+    pub loudness: Option<f64>,
+}
+
+/// The state of a single guild's queue, as it is saved on disk.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    pub tracks: Vec<TrackSnapshot>,
+    /// Playback position of the first track in `tracks`.
+    pub position: Option<Duration>,
+    pub history: Vec<TrackUserData>,
+    pub loop_mode: LoopMode,
+    /// The message of the now playing panel, which keeps being edited after a restart.
+    #[serde(default)]
+    pub panel: Option<(ChannelId, MessageId)>,
+    /// The enabled audio filters, which stay on when the bot leaves and joins again.
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+}
+
+impl QueueSnapshot {
+    /// Is there anything worth saving?
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty() && self.history.is_empty() && self.filters.is_empty()
+    }
+}
+
+/// Stores queue snapshots in a directory, one JSON file per guild.
+#[derive(Clone, Debug)]
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    /// Create a store which keeps its files in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, guild_id: GuildId) -> PathBuf {
+        self.dir.join(format!("{guild_id}.json"))
+    }
+
+    /// Load all saved snapshots.
+    ///
+    /// Files which cannot be parsed are skipped.
+    pub fn load_all(&self) -> Result_<HashMap<GuildId, QueueSnapshot>> {
+        let mut snapshots = HashMap::new();
+
+        if !self.dir.exists() {
+            return Ok(snapshots);
+        }
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(guild_id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            else {
+                continue;
+            };
+
+            match serde_json::from_slice(&std::fs::read(&path)?) {
+                Ok(snapshot) => {
+                    snapshots.insert(GuildId::new(guild_id), snapshot);
+                }
+                Err(e) => println!("could not load queue snapshot {}: {e}", path.display()),
+            }
+        }
+
+        Ok(snapshots)
+    }
+
+    /// Save the snapshot of a single guild, removing the old file if there's nothing to save.
+    pub fn save(&self, guild_id: GuildId, snapshot: &QueueSnapshot) -> Result_<()> {
+        let path = self.path(guild_id);
+
+        if snapshot.is_empty() {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+
+        write_atomic(&path, &serde_json::to_vec(snapshot)?)?;
+
+        Ok(())
+    }
+
+    /// Take a snapshot of every queue and save it.
+    pub async fn save_all(&self, qs: &Mutex<HashMap<GuildId, TrackQueue>>) {
+        let queues = qs
+            .lock()
+            .iter()
+            .map(|(guild_id, queue)| (*guild_id, queue.clone()))
+            .collect::<Vec<_>>();
+
+        for (guild_id, queue) in queues {
+            let snapshot = queue.snapshot().await;
+            if let Err(e) = self.save(guild_id, &snapshot) {
+                println!("could not save queue of guild {guild_id}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_writes() {
+        let dir = std::env::temp_dir().join(format!("scumbo-persist-{}", std::process::id()));
+        let path = dir.join("1.json");
+
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // Only the file itself is left, the temporary one was renamed.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
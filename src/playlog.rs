@@ -0,0 +1,356 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use rusqlite::{Connection, params};
+use serenity::all::{GuildId, UserId};
+
+use crate::history::TrackUserData;
+
+/// An optional on-disk log of every track played by the bot.
+///
+/// Unlike `History`, which only keeps the last few tracks in memory, the log is kept forever and
+/// is used for searching the history and computing statistics.
+#[derive(Clone, Debug)]
+pub struct PlayLog {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// A single record in the `PlayLog`.
+#[derive(Clone, Debug)]
+pub struct Play {
+    pub requester: Option<UserId>,
+    /// UNIX timestamp, in seconds.
+    pub played_at: u64,
+    pub source: String,
+    pub title: String,
+    pub url: String,
+    pub listened: Duration,
+}
+
+impl Play {
+    /// Describe the play in a single line of markdown, e.g.
+    /// `[title](url) (YouTube, 2 hours ago, listened for 3:33, requested by @user)`.
+    ///
+    /// Plays are not numbered like the tracks in the history, because `replay` can't find them.
+    pub fn describe(&self) -> String {
+        let requester = self
+            .requester
+            .map(|user| format!(", requested by <@{user}>"))
+            .unwrap_or_default();
+        format!(
+            "[{}]({}) ({}, <t:{}:R>, listened for {}{requester})",
+            self.title,
+            self.url,
+            self.source,
+            self.played_at,
+            crate::utils::format_duration(self.listened),
+        )
+    }
+}
+
+/// Statistics about the tracks played in a single guild.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub plays: u64,
+    pub total_listened: Duration,
+    /// Track titles with their play count, most played first.
+    pub top_tracks: Vec<(String, u64)>,
+    /// Requesters with the number of tracks they requested, most active first.
+    pub top_requesters: Vec<(UserId, u64)>,
+}
+
+/// Filters for searching through the `PlayLog`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlayFilter {
+    pub user: Option<UserId>,
+    /// Only include tracks played at most this long ago.
+    pub since: Option<Duration>,
+    /// Only include tracks whose title contains this.
+    pub title: Option<String>,
+}
+
+impl PlayFilter {
+    /// Parse filters in the form of `[--user <user>] [--since <age>] [title]`.
+    ///
+    /// The user can be a mention or an ID, the age is a number followed by one of `m`, `h`, `d`
+    /// or `w`, e.g. `7d`.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut filter = Self::default();
+        let mut title = vec![];
+        let mut words = input.split_whitespace();
+
+        while let Some(word) = words.next() {
+            match word {
+                "--user" => {
+                    let user = words.next().ok_or("`--user` needs a user.")?;
+                    let id = user
+                        .trim_start_matches("<@")
+                        .trim_start_matches('!')
+                        .trim_end_matches('>')
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&id| id != 0)
+                        .ok_or_else(|| format!("`{user}` is not a user."))?;
+                    filter.user = Some(UserId::new(id));
+                }
+                "--since" => {
+                    let age = words.next().ok_or("`--since` needs an age, e.g. `7d`.")?;
+                    filter.since =
+                        Some(parse_age(age).ok_or_else(|| format!("`{age}` is not an age."))?);
+                }
+                word => title.push(word),
+            }
+        }
+
+        if !title.is_empty() {
+            filter.title = Some(title.join(" "));
+        }
+
+        Ok(filter)
+    }
+
+    /// Are there no filters at all?
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Parse an age such as `30m`, `12h`, `7d` or `2w`.
+fn parse_age(input: &str) -> Option<Duration> {
+    let (split, unit) = input.char_indices().next_back()?;
+    let number = input[..split].parse::<u64>().ok()?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(number.checked_mul(seconds)?))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl PlayLog {
+    /// Open the log stored at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS plays (
+                id INTEGER PRIMARY KEY,
+                guild_id INTEGER NOT NULL,
+                requester_id INTEGER,
+                played_at INTEGER NOT NULL,
+                source TEXT NOT NULL,
+                title TEXT NOT NULL,
+                url TEXT NOT NULL,
+                listened_ms INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS plays_guild ON plays (guild_id, played_at);",
+        )?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run `f` on the blocking thread pool, because SQLite blocks while it reads and writes.
+    pub async fn run<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&PlayLog) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let log = self.clone();
+        match tokio::task::spawn_blocking(move || f(&log)).await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Record a track which has finished playing.
+    pub fn record(
+        &self,
+        guild_id: GuildId,
+        requester: Option<UserId>,
+        data: &TrackUserData,
+        listened: Duration,
+    ) -> rusqlite::Result<()> {
+        self.conn.lock().execute(
+            "INSERT INTO plays (guild_id, requester_id, played_at, source, title, url, listened_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                guild_id.get() as i64,
+                requester.map(|id| id.get() as i64),
+                now() as i64,
+                data.source(),
+                data.title(),
+                data.url(),
+                listened.as_millis() as i64,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Find up to `limit` tracks played in a guild which match `filter`, newest first.
+    pub fn search(
+        &self,
+        guild_id: GuildId,
+        filter: &PlayFilter,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<Play>> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(
+            "SELECT requester_id, played_at, source, title, url, listened_ms FROM plays
+            WHERE guild_id = ?1
+                AND (?2 IS NULL OR requester_id = ?2)
+                AND (?3 IS NULL OR played_at >= ?3)
+                AND (?4 IS NULL OR title LIKE '%' || ?4 || '%')
+            ORDER BY played_at DESC, id DESC
+            LIMIT ?5",
+        )?;
+
+        let rows = statement.query_map(
+            params![
+                guild_id.get() as i64,
+                filter.user.map(|id| id.get() as i64),
+                filter
+                    .since
+                    .map(|since| now().saturating_sub(since.as_secs()) as i64),
+                filter.title,
+                limit as i64,
+            ],
+            |row| {
+                Ok(Play {
+                    requester: row
+                        .get::<_, Option<i64>>(0)?
+                        .map(|id| UserId::new(id as u64)),
+                    played_at: row.get::<_, i64>(1)? as u64,
+                    source: row.get(2)?,
+                    title: row.get(3)?,
+                    url: row.get(4)?,
+                    listened: Duration::from_millis(row.get::<_, i64>(5)? as u64),
+                })
+            },
+        )?;
+
+        rows.collect()
+    }
+
+    /// Compute the statistics of a single guild.
+    pub fn stats(&self, guild_id: GuildId, top: usize) -> rusqlite::Result<Stats> {
+        let conn = self.conn.lock();
+        let guild_id = guild_id.get() as i64;
+
+        let (plays, listened_ms) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(listened_ms), 0) FROM plays WHERE guild_id = ?1",
+            params![guild_id],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )?;
+
+        let top_tracks = conn
+            .prepare(
+                "SELECT MAX(title), COUNT(*) AS n FROM plays WHERE guild_id = ?1
+                GROUP BY url ORDER BY n DESC LIMIT ?2",
+            )?
+            .query_map(params![guild_id, top as i64], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let top_requesters = conn
+            .prepare(
+                "SELECT requester_id, COUNT(*) AS n FROM plays
+                WHERE guild_id = ?1 AND requester_id IS NOT NULL
+                GROUP BY requester_id ORDER BY n DESC LIMIT ?2",
+            )?
+            .query_map(params![guild_id, top as i64], |row| {
+                Ok((
+                    UserId::new(row.get::<_, i64>(0)? as u64),
+                    row.get::<_, i64>(1)? as u64,
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(Stats {
+            plays: plays as u64,
+            total_listened: Duration::from_millis(listened_ms as u64),
+            top_tracks,
+            top_requesters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::SourceKind;
+
+    #[test]
+    fn filters() {
+        assert!(PlayFilter::parse("").unwrap().is_empty());
+        assert_eq!(
+            PlayFilter::parse("--user <@!42> --since 2d never gonna").unwrap(),
+            PlayFilter {
+                user: Some(UserId::new(42)),
+                since: Some(Duration::from_secs(2 * 24 * 60 * 60)),
+                title: Some("never gonna".into()),
+            }
+        );
+        assert!(PlayFilter::parse("--since 2y").is_err());
+        assert!(PlayFilter::parse("--since 5é").is_err());
+        assert!(PlayFilter::parse("--since 99999999999999999w").is_err());
+        assert!(PlayFilter::parse("--user").is_err());
+    }
+
+    #[test]
+    fn record_and_search() {
+        let log = PlayLog::open(":memory:").unwrap();
+        let guild_id = GuildId::new(1);
+        let track = TrackUserData::new(
+            SourceKind::HttpStream,
+            "Radio".into(),
+            "https://example.com/radio".into(),
+            None,
+        );
+
+        log.record(
+            guild_id,
+            Some(UserId::new(7)),
+            &track,
+            Duration::from_secs(90),
+        )
+        .unwrap();
+        log.record(guild_id, None, &track, Duration::from_secs(30))
+            .unwrap();
+
+        let filter = PlayFilter::parse("--user 7 radio").unwrap();
+        let plays = log.search(guild_id, &filter, 10).unwrap();
+        assert_eq!(plays.len(), 1);
+        // `replay` indexes the in-memory history, so plays must not look like they have an index.
+        assert_eq!(
+            plays[0].describe(),
+            format!(
+                "[Radio](https://example.com/radio) (http stream, <t:{}:R>, listened for 1:30, \
+                 requested by <@7>)",
+                plays[0].played_at
+            )
+        );
+
+        let stats = log.stats(guild_id, 5).unwrap();
+        assert_eq!(stats.plays, 2);
+        assert_eq!(stats.total_listened, Duration::from_secs(120));
+        assert_eq!(stats.top_tracks[0].1, 2);
+        assert_eq!(stats.top_requesters, vec![(UserId::new(7), 1)]);
+    }
+}
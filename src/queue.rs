@@ -1,37 +1,229 @@
 use crate::{
     Result_,
-    history::{History, TrackUserData},
+    config::Config,
+    error::ScumboError,
+    filters::{FilteredInput, Filters},
+    history::{History, SourceKind, TrackUserData},
+    metadata,
+    persist::{QueueSnapshot, TrackSnapshot},
+    playlog::PlayLog,
+    settings::GuildSettings,
+    vote::{Ballot, SkipVote},
 };
 use parking_lot::Mutex;
 use rand::random_range;
-use serenity::all::Attachment;
+use serde::{Deserialize, Serialize};
+use serenity::all::{Attachment, ChannelId, GuildId, MessageId, UserId};
 use songbird::{
+    Call,
     driver::Driver,
     events::{Event, EventData, TrackEvent},
     input::Input,
-    tracks::{Track, TrackHandle, TrackResult},
+    tracks::{PlayMode, Track, TrackHandle, TrackResult},
 };
-use std::{collections::VecDeque, ops::Deref, sync::Arc, time::Duration};
+use std::{
+    collections::{HashSet, VecDeque},
+    ops::RangeInclusive,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+/// How long before a crossfade the next track is loaded.
+const PRELOAD_MARGIN: Duration = Duration::from_secs(3);
+
+/// How often the volume changes during a fade.
+const FADE_STEP: Duration = Duration::from_millis(50);
+
+/// How often a waiting crossfade checks whether it is time to start, when the track is paused.
+const FADE_POLL: Duration = Duration::from_millis(250);
 
 #[derive(Clone, Debug, Default)]
 pub struct TrackQueue {
     pub inner: Arc<Mutex<TrackQueueCore>>,
 }
 
+/// A track waiting in the queue.
+///
+/// Only the recipe of a track (its `TrackUserData`) is kept until the track is about to play. Only
+/// then a fresh `Track` is built from it and registered with the driver.
 #[derive(Debug)]
-pub struct Queued(pub TrackHandle);
+pub struct Queued {
+    data: Arc<TrackUserData>,
+    handle: Option<TrackHandle>,
+    /// Volume of just this track, overriding the volume of the queue.
+    volume: Option<f32>,
+    /// Measured loudness of the track in LUFS, once it is known.
+    loudness: Option<f64>,
+    /// Whether the track was built to run through the filters, it is decoded by songbird itself
+    /// if none were enabled.
+    filtered: bool,
+    /// How much faster than normal the track was built to play, because of the speed filters.
+    speed: f64,
+}
 
-impl Deref for Queued {
-    type Target = TrackHandle;
+/// Where in the queue a newly added track should be put.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Position {
+    /// At the end of the queue.
+    #[default]
+    Back,
+    /// Right after the currently playing track.
+    Next,
+    /// In front of the whole queue, the track starts playing immediately.
+    Front,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+/// What happens with a track after it finishes playing.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum LoopMode {
+    /// The track is stored in the history and the queue moves on.
+    #[default]
+    #[name = "off"]
+    Off,
+    /// The track is played again.
+    #[name = "track"]
+    Track,
+    /// The track is put at the back of the queue.
+    #[name = "queue"]
+    Queue,
+}
+
+/// How the queue goes from one track to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    /// The next track starts once the current one has ended and the driver is free.
+    #[default]
+    Off,
+    /// The preloaded next track starts the moment the current one ends.
+    ///
+    /// The start is timed from the duration of the current track. Tracks of unknown length, like
+    /// radio streams, can't be timed, so the next track starts once their end is noticed.
+    Gapless,
+    /// The next track fades in while the current one fades out, over this many seconds.
+    Crossfade(u8),
+}
+
+impl Transition {
+    /// The longest crossfade a guild can choose, in seconds.
+    pub const MAX_CROSSFADE: u8 = 12;
+
+    /// Parse `off`, `gapless` or the length of a crossfade in seconds, e.g. `5s`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Some(Transition::Off),
+            "gapless" => Some(Transition::Gapless),
+            seconds => seconds
+                .trim_end_matches('s')
+                .parse()
+                .ok()
+                .filter(|seconds| (1..=Self::MAX_CROSSFADE).contains(seconds))
+                .map(Transition::Crossfade),
+        }
+    }
+
+    /// Describe the transition, e.g. `crossfade of 5s`.
+    pub fn name(self) -> String {
+        match self {
+            Transition::Off => "off".into(),
+            Transition::Gapless => "gapless".into(),
+            Transition::Crossfade(seconds) => format!("crossfade of {seconds}s"),
+        }
+    }
+
+    /// Get the length of the crossfade, if crossfading.
+    pub fn crossfade(self) -> Option<Duration> {
+        match self {
+            Transition::Crossfade(seconds) => Some(Duration::from_secs(seconds.into())),
+            _ => None,
+        }
+    }
+
+    /// Get how long the next track overlaps with the end of the current one, if its start is
+    /// timed at all. Gapless transitions overlap for no time.
+    pub fn overlap(self) -> Option<Duration> {
+        match self {
+            Transition::Off => None,
+            Transition::Gapless => Some(Duration::ZERO),
+            Transition::Crossfade(_) => self.crossfade(),
+        }
     }
 }
 
 impl Queued {
-    pub fn handle(&self) -> TrackHandle {
-        self.0.clone()
+    /// Create a new queue entry which is not registered with the driver yet.
+    pub fn new(data: TrackUserData) -> Self {
+        Self {
+            data: Arc::new(data),
+            handle: None,
+            volume: None,
+            loudness: None,
+            filtered: false,
+            speed: 1.0,
+        }
+    }
+
+    /// Create a new entry for the same track, which is not registered with the driver yet.
+    ///
+    /// The volume override and the measured loudness are kept.
+    pub fn fresh(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            handle: None,
+            volume: self.volume,
+            loudness: self.loudness,
+            filtered: false,
+            speed: 1.0,
+        }
+    }
+
+    /// Get the gain which brings the track to the `target` loudness, in decibels.
+    ///
+    /// `None` if normalisation is off or the loudness of the track is not known yet.
+    fn gain_db(&self, target: Option<f64>) -> Option<f64> {
+        Some(crate::loudness::gain_db(self.loudness?, target?))
+    }
+
+    /// Get the volume the track plays at, including its normalisation gain.
+    fn effective_volume(&self, volume: f32, target: Option<f64>) -> f32 {
+        let gain = self
+            .gain_db(target)
+            .map_or(1.0, crate::loudness::db_to_volume);
+        self.volume.unwrap_or(volume) * gain
+    }
+
+    /// Get the recipe of the track.
+    pub fn data(&self) -> Arc<TrackUserData> {
+        self.data.clone()
+    }
+
+    /// Get how long the track plays for at the speed it was built with, if known.
+    pub fn play_duration(&self) -> Option<Duration> {
+        self.data
+            .duration
+            .map(|duration| duration.div_f64(self.speed))
+    }
+
+    /// Get the handle of the track, if it was already registered with the driver.
+    pub fn handle(&self) -> Option<TrackHandle> {
+        self.handle.clone()
+    }
+
+    /// Stop the track and forget its handle, so that a fresh track is built the next time it
+    /// should play.
+    fn reset(&mut self) {
+        drop(self.stop());
+        self.handle = None;
+    }
+
+    /// Stop the track, if it was registered with the driver.
+    fn stop(&self) -> TrackResult<()> {
+        match self.handle {
+            Some(ref handle) => handle.stop(),
+            None => Ok(()),
+        }
     }
 }
 
@@ -39,6 +231,39 @@ impl Queued {
 pub struct TrackQueueCore {
     pub queued_tracks: VecDeque<Queued>,
     pub history: History,
+    pub loop_mode: LoopMode,
+    /// The voice call the queue plays into, used to register tracks with the driver.
+    pub call: Weak<tokio::sync::Mutex<Call>>,
+    pub client: reqwest::Client,
+    pub guild_id: GuildId,
+    /// How long before the end of a track the next one starts loading.
+    pub preload_offset: Duration,
+    /// Volume of every track without a volume override.
+    pub volume: f32,
+    /// Loudness every track is normalised to in LUFS, if normalisation is on.
+    pub loudness_target: Option<f64>,
+    /// How one track turns into the next.
+    pub transition: Transition,
+    /// Audio filters applied to every track, including the one playing.
+    pub filters: Filters,
+    /// How many tracks the queue can hold, if limited.
+    pub max_length: Option<usize>,
+    /// The running vote to skip the current track, if any.
+    pub skip_vote: Option<SkipVote>,
+    /// The message of the now playing panel, once it has been sent.
+    pub panel: Option<(ChannelId, MessageId)>,
+    /// Whether the panel shows that nothing is playing, so there is nothing to refresh.
+    pub panel_idle: bool,
+    /// The last known position of the current track, to continue from after a lost connection.
+    pub last_position: Option<(Arc<TrackUserData>, Duration)>,
+    /// Whether the voice connection was lost, the position is not tracked until it is back.
+    pub reconnecting: bool,
+    /// The member the bot moves along with between voice channels, if any.
+    pub following: Option<UserId>,
+    /// Every finished track is recorded here, if enabled.
+    pub play_log: Option<PlayLog>,
+    /// Tracks which were playing when they were skipped, their end still counts as a play.
+    pub skipped: Vec<TrackHandle>,
 }
 
 pub struct QueueHandler {
@@ -50,90 +275,305 @@ pub struct SongPreloader {
 }
 
 impl TrackQueue {
-    /// Create a new track queue.
-    pub fn new(history_capacity: usize) -> Self {
+    /// Create a new track queue, set up according to the guild's settings.
+    pub fn new(
+        settings: &GuildSettings,
+        config: &Config,
+        guild_id: GuildId,
+        call: &Arc<tokio::sync::Mutex<Call>>,
+        client: reqwest::Client,
+        play_log: Option<PlayLog>,
+    ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(TrackQueueCore {
                 queued_tracks: VecDeque::new(),
-                history: History::new(history_capacity),
+                history: History::new(settings.history_capacity(config)),
+                loop_mode: settings.loop_mode,
+                call: Arc::downgrade(call),
+                client,
+                guild_id,
+                preload_offset: config.preload_offset,
+                volume: settings.volume(),
+                loudness_target: settings.loudness_target(config),
+                transition: settings.transition,
+                filters: Filters::default(),
+                max_length: settings.max_queue_length,
+                skip_vote: None,
+                panel: None,
+                panel_idle: false,
+                last_position: None,
+                reconnecting: false,
+                following: None,
+                play_log,
+                skipped: vec![],
             })),
         }
     }
 
+    /// Change the queue according to updated guild settings.
+    ///
+    /// The loop mode is only used for new queues.
+    pub fn apply_settings(&self, settings: &GuildSettings, config: &Config) {
+        {
+            let mut inner = self.inner.lock();
+
+            inner
+                .history
+                .set_capacity(settings.history_capacity(config));
+            inner.max_length = settings.max_queue_length;
+            inner.loudness_target = settings.loudness_target(config);
+            inner.transition = settings.transition;
+        }
+
+        // Also applies a changed loudness target to the registered tracks.
+        self.set_volume(settings.volume());
+    }
+
+    /// Fail if the queue can't take any more tracks.
+    pub fn check_full(&self) -> Result_<()> {
+        let inner = self.inner.lock();
+
+        match inner.max_length {
+            Some(max) if inner.queued_tracks.len() >= max => Err(ScumboError::QueueFull(max)),
+            _ => Ok(()),
+        }
+    }
+
     /// Try to add a track supplied to the bot as an attachment.
+    ///
+    /// The title, artist and duration are read from the tags of the file, if it has them.
     pub async fn add_from_attachment(
         &self,
         attachment: Attachment,
-        driver: &mut Driver,
-    ) -> Result_<TrackHandle> {
-        let data = attachment.download().await?;
-        let user_data = TrackUserData::Attachment {
-            title: attachment.filename.clone(),
-            attachment_url: attachment.url.clone(),
-        };
-        let track = Track::new_with_data(data.into(), Arc::new(user_data));
-        Ok(self.add(track, driver).await)
+        requester: UserId,
+    ) -> Result_<TrackUserData> {
+        let duration = attachment.duration_secs.map(Duration::from_secs_f64);
+        self.add_from_file(attachment.url, attachment.filename, duration, requester)
+            .await
+    }
+
+    /// Add a file which was attached to a message before, e.g. to play it again.
+    pub async fn add_from_file_url(
+        &self,
+        url: String,
+        requester: UserId,
+    ) -> Result_<TrackUserData> {
+        let filename = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|path| path.rsplit('/').next())
+            .unwrap_or_default()
+            .to_string();
+        self.add_from_file(url, filename, None, requester).await
+    }
+
+    async fn add_from_file(
+        &self,
+        url: String,
+        filename: String,
+        duration: Option<Duration>,
+        requester: UserId,
+    ) -> Result_<TrackUserData> {
+        self.check_full()?;
+        let client = self.inner.lock().client.clone();
+        let extension = filename.rsplit_once('.').map(|(_, ext)| ext);
+        let tags = metadata::attachment(&client, &url, extension).await;
+
+        let mut user_data = TrackUserData::new(
+            SourceKind::Attachment,
+            tags.title.unwrap_or(filename),
+            url,
+            Some(requester),
+        );
+        user_data.artist = tags.artist;
+        user_data.duration = tags.duration.or(duration);
+
+        self.enqueue(Queued::new(user_data.clone()), Position::Back)
+            .await?;
+        Ok(user_data)
     }
 
     /// Add a track from a `YouTube` search.
     pub async fn add_from_youtube(
         &self,
         mut input: Input,
-        driver: &mut Driver,
-    ) -> Result_<TrackHandle> {
+        requester: UserId,
+        position: Position,
+    ) -> Result_<TrackUserData> {
+        // Don't bother fetching the metadata if the track can't be added anyway.
+        self.check_full()?;
         let metadata = input.aux_metadata().await?;
-        let user_data = TrackUserData::Youtube {
-            url: metadata.source_url.unwrap_or_default(),
-            title: metadata.title.unwrap_or_else(|| "Unknown track".into()),
-        };
 
-        let track = Track::new_with_data(input, Arc::new(user_data));
-        Ok(self.add(track, driver).await)
+        let mut user_data = TrackUserData::new(
+            SourceKind::Youtube,
+            metadata.title.unwrap_or_else(|| "Unknown track".into()),
+            metadata.source_url.unwrap_or_default(),
+            Some(requester),
+        );
+        user_data.duration = metadata.duration;
+        user_data.artist = metadata.artist;
+        user_data.thumbnail = metadata.thumbnail;
+        user_data.uploader = metadata.channel;
+
+        // The driver is only locked once the metadata is there, resolving it can take a while.
+        self.enqueue(Queued::new(user_data.clone()), position)
+            .await?;
+        Ok(user_data)
     }
 
     /// Add a track from an HTTP request.
-    pub async fn add_from_stream(
-        &self,
-        input: Input,
-        url: String,
-        driver: &mut Driver,
-    ) -> Result_<TrackHandle> {
-        let user_data = TrackUserData::HttpStream { url };
-        let track = Track::new_with_data(input, Arc::new(user_data));
-        Ok(self.add(track, driver).await)
+    ///
+    /// The title is the station name from the ICY headers or the file name, if the server sends
+    /// them.
+    pub async fn add_from_stream(&self, url: String, requester: UserId) -> Result_<TrackUserData> {
+        self.check_full()?;
+        let client = self.inner.lock().client.clone();
+        let info = metadata::stream(&client, &url).await;
+
+        let mut user_data = TrackUserData::new(
+            SourceKind::HttpStream,
+            info.title.unwrap_or_else(|| url.clone()),
+            url,
+            Some(requester),
+        );
+        user_data.uploader = info.description;
+
+        self.enqueue(Queued::new(user_data.clone()), Position::Back)
+            .await?;
+        Ok(user_data)
+    }
+
+    /// Put an already existing recipe back into the queue at `position`.
+    ///
+    /// This is used for tracks which have already ended, because their `TrackHandle` cannot be
+    /// played again.
+    pub async fn requeue(&self, queued: Queued, position: Position) {
+        self.insert(queued, position);
+        self.play_front().await;
+    }
+
+    /// Put `queued` into the queue, starting it if nothing else is playing.
+    ///
+    /// The driver is only locked once the track is in the queue.
+    async fn enqueue(&self, queued: Queued, position: Position) -> Result_<()> {
+        self.check_full()?;
+        if self.insert(queued, position) == 0 {
+            self.play_front().await;
+        }
+
+        Ok(())
+    }
+
+    /// Put `queued` into the queue, returning its index.
+    fn insert(&self, queued: Queued, position: Position) -> usize {
+        let mut inner = self.inner.lock();
+
+        match position {
+            Position::Next if !inner.queued_tracks.is_empty() => {
+                inner.queued_tracks.insert(1, queued);
+                1
+            }
+            Position::Front => {
+                inner.queued_tracks.push_front(queued);
+                0
+            }
+            _ => {
+                inner.queued_tracks.push_back(queued);
+                inner.queued_tracks.len() - 1
+            }
+        }
     }
 
-    async fn add(&self, mut track: Track, driver: &mut Driver) -> TrackHandle {
-        let preload_time = Self::get_preload_time(&mut track).await;
-        self.add_with_preload(track, driver, preload_time)
+    /// Make sure the first track in the queue is registered with the driver and playing.
+    pub async fn play_front(&self) {
+        let Some(call) = self.inner.lock().call.upgrade() else {
+            return;
+        };
+        let mut driver = call.lock().await;
+
+        self.play_front_with(&mut driver);
     }
 
-    async fn get_preload_time(track: &mut Track) -> Option<Duration> {
-        let meta = match track.input {
-            Input::Lazy(ref mut rec) | Input::Live(_, Some(ref mut rec)) => {
-                rec.aux_metadata().await.ok()
+    /// Keep going until we find one track which works, or we run out.
+    fn play_front_with(&self, driver: &mut Driver) {
+        let mut inner = self.inner.lock();
+
+        while let Some(front) = inner.queued_tracks.front() {
+            let handle = match front.handle() {
+                Some(handle) => handle,
+                None => self.register(&mut inner, 0, driver),
+            };
+
+            if handle.play().is_err() {
+                // Discard files which cannot be used for whatever reason.
+                inner.queued_tracks.pop_front();
+            } else {
+                break;
             }
-            Input::Live(_, None) => None,
+        }
+    }
+
+    /// Make sure the track after the current one is registered with the driver, so that it can
+    /// start without delay.
+    pub async fn preload_next(&self) {
+        let Some(call) = self.inner.lock().call.upgrade() else {
+            return;
         };
+        let mut driver = call.lock().await;
 
-        meta.and_then(|meta| meta.duration)
-            .map(|d| d.saturating_sub(Duration::from_secs(5)))
+        let mut inner = self.inner.lock();
+        if let Some(next) = inner.queued_tracks.get(1) {
+            let handle = match next.handle() {
+                Some(handle) => handle,
+                None => self.register(&mut inner, 1, &mut driver),
+            };
+
+            // This is the sync-version so that we can fire and ignore
+            drop(handle.make_playable());
+        }
     }
 
-    #[inline]
-    fn add_with_preload(
+    /// Build a fresh `Track` from the recipe of the queued track at `index` and register it with
+    /// the driver, paused.
+    fn register(
         &self,
-        mut track: Track,
+        inner: &mut TrackQueueCore,
+        index: usize,
         driver: &mut Driver,
-        preload_time: Option<Duration>,
     ) -> TrackHandle {
+        // The next track has to be loaded before a crossfade starts.
+        let crossfade = inner.transition.crossfade().unwrap_or_default();
+        let (client, preload_offset, volume, target) = (
+            inner.client.clone(),
+            inner.preload_offset.max(crossfade + PRELOAD_MARGIN),
+            inner.volume,
+            inner.loudness_target,
+        );
+        let filters = inner.filters.clone();
+        let queued = &mut inner.queued_tracks[index];
+        // Without filters there is no need to decode the track ourselves.
+        queued.filtered = !filters.is_empty();
+        queued.speed = filters.speed();
+        let input = if queued.filtered {
+            FilteredInput::new(queued.data(), client.clone(), filters).into()
+        } else {
+            queued.data().input(client.clone())
+        };
+        let mut track = Track::new_with_data(input, queued.data())
+            .volume(queued.effective_volume(volume, target));
+
+        // The track starts at its plain volume and is corrected once its loudness is known.
+        if target.is_some() && queued.loudness.is_none() {
+            self.measure_loudness(queued.data(), client);
+        }
+
         let remote_lock = self.inner.clone();
         track.events.add_event(
             EventData::new(Event::Track(TrackEvent::End), QueueHandler { remote_lock }),
             Duration::ZERO,
         );
 
-        if let Some(time) = preload_time {
+        if let Some(time) = Self::get_preload_time(queued.play_duration(), preload_offset) {
             let remote_lock = self.inner.clone();
             track.events.add_event(
                 EventData::new(Event::Delayed(time), SongPreloader { remote_lock }),
@@ -141,28 +581,246 @@ impl TrackQueue {
             );
         }
 
-        let (should_play, handle) = {
+        let handle = driver.play(track.pause());
+        queued.handle = Some(handle.clone());
+
+        handle
+    }
+
+    /// Measure the loudness of the track with `data` in the background, then apply its
+    /// normalisation gain.
+    fn measure_loudness(&self, data: Arc<TrackUserData>, client: reqwest::Client) {
+        let remote_lock = self.inner.clone();
+
+        tokio::spawn(async move {
+            let Some(loudness) = crate::loudness::measure(data.input(client)).await else {
+                return;
+            };
+
+            let mut inner = remote_lock.lock();
+            let (volume, target) = (inner.volume, inner.loudness_target);
+            // The track might have been registered again, or left the queue, in the meantime.
+            for track in inner
+                .queued_tracks
+                .iter_mut()
+                .filter(|track| Arc::ptr_eq(&track.data, &data))
+            {
+                track.loudness = Some(loudness);
+                if let Some(handle) = &track.handle {
+                    drop(handle.set_volume(track.effective_volume(volume, target)));
+                }
+            }
+        });
+    }
+
+    /// Build the registered tracks again if they were built with filters and none are enabled
+    /// anymore, or the other way around, or if the speed changed.
+    ///
+    /// The current track continues where it was, other tracks are built again when they play.
+    pub async fn refilter(&self) {
+        let Some(call) = self.inner.lock().call.upgrade() else {
+            return;
+        };
+        let mut driver = call.lock().await;
+
+        let current = {
             let mut inner = self.inner.lock();
+            let built = (!inner.filters.is_empty(), inner.filters.speed());
+            for track in inner.queued_tracks.iter_mut().skip(1) {
+                if track.handle.is_some() && (track.filtered, track.speed) != built {
+                    track.reset();
+                }
+            }
+            inner
+                .queued_tracks
+                .front()
+                .filter(|track| (track.filtered, track.speed) != built)
+                .and_then(|track| Some((track.handle()?, track.speed)))
+        };
+        let Some((current, speed)) = current else {
+            return;
+        };
+        let Ok(info) = current.get_info().await else {
+            return;
+        };
+
+        match self.inner.lock().queued_tracks.front_mut() {
+            // The track might have ended in the meantime.
+            Some(front) if front.handle().is_some_and(|h| h.uuid() == current.uuid()) => {
+                front.reset()
+            }
+            _ => return,
+        }
+        self.play_front_with(&mut driver);
+
+        // Positions are in the time the listeners hear, which a new speed stretches or shrinks.
+        let position = info.position.mul_f64(speed / self.speed());
+        if let Some(handle) = self.current() {
+            if info.playing == PlayMode::Pause {
+                drop(handle.pause());
+            }
+            drop(handle.seek(position));
+        }
+    }
 
-            let handle = driver.play(track.pause());
-            inner.queued_tracks.push_back(Queued(handle.clone()));
+    /// Fade the next track in over the end of the current one, if crossfading is on, or start it
+    /// right as the current one ends, if the transition is gapless.
+    ///
+    /// The start of the fade is worked out from the position of the current track, so that
+    /// seeking and pausing are accounted for. The current track is stopped once it faded out.
+    pub async fn crossfade(&self) {
+        let (length, current, next, duration) = {
+            let inner = self.inner.lock();
+            let Some(length) = inner.transition.overlap() else {
+                return;
+            };
+            // The next track is not the one after this one when it loops.
+            if inner.loop_mode == LoopMode::Track {
+                return;
+            }
+            let (Some(current), Some(next)) = (
+                inner.queued_tracks.front(),
+                inner.queued_tracks.get(1).and_then(Queued::handle),
+            ) else {
+                return;
+            };
+            let (Some(handle), Some(duration)) = (current.handle(), current.play_duration()) else {
+                return;
+            };
+
+            (length, handle, next, duration)
+        };
 
-            (inner.queued_tracks.len() == 1, handle)
+        let remaining = loop {
+            // Fails once the track has ended or was stopped.
+            let Ok(info) = current.get_info().await else {
+                return;
+            };
+            let remaining = duration.saturating_sub(info.position);
+            if info.playing == PlayMode::Play && remaining <= length + FADE_POLL {
+                break remaining;
+            }
+            tokio::time::sleep(remaining.saturating_sub(length).max(FADE_POLL)).await;
         };
+        tokio::time::sleep(remaining.saturating_sub(length)).await;
+        let length = length.min(remaining);
+
+        let is_current = || self.current().is_some_and(|h| h.uuid() == current.uuid());
+        let Some((from, to)) = ({
+            let inner = self.inner.lock();
+            let volume = |index: usize| {
+                let track = inner.queued_tracks.get(index)?;
+                Some(track.effective_volume(inner.volume, inner.loudness_target))
+            };
+            let next_is_next = inner
+                .queued_tracks
+                .get(1)
+                .and_then(Queued::handle)
+                .is_some_and(|h| h.uuid() == next.uuid());
 
-        if should_play {
-            drop(handle.play());
+            // The queue might have changed while waiting.
+            if next_is_next && is_current_locked(&inner, &current) {
+                volume(0).zip(volume(1))
+            } else {
+                None
+            }
+        }) else {
+            return;
+        };
+
+        // Gapless, or the track ended already, so the next one takes over at full volume.
+        if length.is_zero() {
+            if next.play().is_ok() {
+                drop(current.stop());
+            }
+            return;
         }
 
-        handle
+        drop(next.set_volume(0.0));
+        if next.play().is_err() {
+            return;
+        }
+        fade(length, |progress| {
+            if !is_current() {
+                return false;
+            }
+            drop(current.set_volume(from * (1.0 - progress)));
+            drop(next.set_volume(to * progress));
+            true
+        })
+        .await;
+
+        // Ends the track early if its duration was rounded up, the queue moves on as usual.
+        if is_current() {
+            drop(current.stop());
+        }
+        // The next track gets its full volume, even when the fade was cut short by a skip.
+        self.reset_volume(&next);
+    }
+
+    /// Fade in the current track over `length`, for transitions which could not be prepared.
+    pub async fn fade_in(&self, length: Duration) {
+        let Some(handle) = self.current() else {
+            return;
+        };
+        let Some(to) = ({
+            let inner = self.inner.lock();
+            inner
+                .queued_tracks
+                .front()
+                .map(|track| track.effective_volume(inner.volume, inner.loudness_target))
+        }) else {
+            return;
+        };
+
+        drop(handle.set_volume(0.0));
+        fade(length, |progress| {
+            let is_current = self.current().is_some_and(|h| h.uuid() == handle.uuid());
+            is_current && handle.set_volume(to * progress).is_ok()
+        })
+        .await;
+        self.reset_volume(&handle);
+    }
+
+    /// Set the volume of the track with `handle` back to what it should be, if it is queued.
+    fn reset_volume(&self, handle: &TrackHandle) {
+        let inner = self.inner.lock();
+        let track = inner
+            .queued_tracks
+            .iter()
+            .find(|track| track.handle().is_some_and(|h| h.uuid() == handle.uuid()));
+        if let Some(track) = track {
+            drop(handle.set_volume(track.effective_volume(inner.volume, inner.loudness_target)));
+        }
+    }
+
+    fn get_preload_time(duration: Option<Duration>, offset: Duration) -> Option<Duration> {
+        duration.map(|d| d.saturating_sub(offset))
+    }
+
+    /// Get how long the current track plays for at its speed, if known.
+    pub fn current_duration(&self) -> Option<Duration> {
+        self.inner
+            .lock()
+            .queued_tracks
+            .front()
+            .and_then(Queued::play_duration)
+    }
+
+    /// Get how much faster than normal the current track plays.
+    pub fn speed(&self) -> f64 {
+        self.inner
+            .lock()
+            .queued_tracks
+            .front()
+            .map_or(1.0, |track| track.speed)
     }
 
     /// Get the currently playing track.
-    #[allow(unused)]
     pub fn current(&self) -> Option<TrackHandle> {
         let inner = self.inner.lock();
 
-        inner.queued_tracks.front().map(Queued::handle)
+        inner.queued_tracks.front().and_then(Queued::handle)
     }
 
     /// Remove track at `index` without adding it to `History`.
@@ -172,7 +830,6 @@ impl TrackQueue {
     }
 
     /// Get the length of the queue.
-    #[allow(unused)]
     pub fn len(&self) -> usize {
         let inner = self.inner.lock();
 
@@ -188,7 +845,6 @@ impl TrackQueue {
     }
 
     /// Run a `func` to modify the queue.
-    #[allow(unused)]
     pub fn modify_queue<F, O>(&self, func: F) -> O
     where
         F: FnOnce(&mut VecDeque<Queued>) -> O,
@@ -197,11 +853,180 @@ impl TrackQueue {
         func(&mut inner.queued_tracks)
     }
 
+    /// Remove the tracks in `range` from the queue, without adding them to `History`.
+    ///
+    /// The currently playing track (index 0) is never removed. Returns the data of removed tracks.
+    #[allow(unused)]
+    pub fn remove(&self, range: RangeInclusive<usize>) -> Vec<TrackUserData> {
+        self.remove_if(range, |_| true).unwrap_or_default()
+    }
+
+    /// Like `remove`, but only if `allowed` accepts the tracks in `range`.
+    ///
+    /// The tracks are checked and removed under the same lock, so the queue can't change in
+    /// between. Returns `None` if the removal was not allowed.
+    pub fn remove_if<F>(
+        &self,
+        range: RangeInclusive<usize>,
+        allowed: F,
+    ) -> Option<Vec<TrackUserData>>
+    where
+        F: FnOnce(&[&TrackUserData]) -> bool,
+    {
+        let start = (*range.start()).max(1);
+        let end = *range.end();
+
+        self.modify_queue(|vq| {
+            if start >= vq.len() || start > end {
+                return Some(vec![]);
+            }
+            let range = start..=end.min(vq.len() - 1);
+
+            let affected = vq
+                .range(range.clone())
+                .map(|track| &*track.data)
+                .collect::<Vec<_>>();
+            if !allowed(&affected) {
+                return None;
+            }
+
+            Some(
+                vq.drain(range)
+                    .map(|track| {
+                        drop(track.stop());
+                        Arc::unwrap_or_clone(track.data)
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    /// Move the track at index `from` to index `to`.
+    ///
+    /// The currently playing track (index 0) cannot be moved and nothing can be moved in front of
+    /// it. Returns `false` if the move was not possible.
+    pub fn move_track(&self, from: usize, to: usize) -> bool {
+        self.modify_queue(|vq| {
+            if from == 0 || to == 0 || from >= vq.len() || to >= vq.len() {
+                return false;
+            }
+
+            if let Some(track) = vq.remove(from) {
+                vq.insert(to, track);
+            }
+            true
+        })
+    }
+
+    /// Swap the tracks at indices `a` and `b`.
+    ///
+    /// The currently playing track (index 0) cannot be swapped. Returns `false` if the swap was not
+    /// possible.
+    pub fn swap(&self, a: usize, b: usize) -> bool {
+        self.modify_queue(|vq| {
+            if a == 0 || b == 0 || a >= vq.len() || b >= vq.len() {
+                return false;
+            }
+
+            vq.swap(a, b);
+            true
+        })
+    }
+
+    /// Remove all tracks which have the same source URL as a track earlier in the queue.
+    ///
+    /// Returns the number of removed tracks.
+    pub fn dedupe(&self) -> usize {
+        self.modify_queue(|vq| {
+            let mut seen = HashSet::new();
+            let before = vq.len();
+
+            vq.retain(|track| {
+                let unique = seen.insert(track.data.url());
+                if !unique {
+                    drop(track.stop());
+                }
+                unique
+            });
+
+            before - vq.len()
+        })
+    }
+
+    /// Get the current loop mode.
+    pub fn loop_mode(&self) -> LoopMode {
+        self.inner.lock().loop_mode
+    }
+
+    /// Set what happens with tracks after they finish playing.
+    pub fn set_loop_mode(&self, mode: LoopMode) {
+        self.inner.lock().loop_mode = mode;
+    }
+
+    /// Get the volume of the queue, where `1.0` is the unchanged volume.
+    pub fn volume(&self) -> f32 {
+        self.inner.lock().volume
+    }
+
+    /// Get the volume override of the current track, if it has one.
+    pub fn track_volume(&self) -> Option<f32> {
+        self.inner.lock().queued_tracks.front()?.volume
+    }
+
+    /// Change the volume of the queue, including the tracks which are already playing or
+    /// preloaded, unless they have a volume override.
+    pub fn set_volume(&self, volume: f32) {
+        let mut inner = self.inner.lock();
+
+        inner.volume = volume;
+        let target = inner.loudness_target;
+        for track in &inner.queued_tracks {
+            if let Some(handle) = &track.handle {
+                // Fails if the track already ended, so there is nothing to change anyway.
+                drop(handle.set_volume(track.effective_volume(volume, target)));
+            }
+        }
+    }
+
+    /// Override the volume of the current track, or go back to the queue's volume with `None`.
+    ///
+    /// Returns `false` if nothing is playing.
+    pub fn set_track_volume(&self, volume: Option<f32>) -> bool {
+        let mut inner = self.inner.lock();
+        let (queue_volume, target) = (inner.volume, inner.loudness_target);
+        let Some(current) = inner.queued_tracks.front_mut() else {
+            return false;
+        };
+
+        current.volume = volume;
+        if let Some(handle) = &current.handle {
+            drop(handle.set_volume(current.effective_volume(queue_volume, target)));
+        }
+
+        true
+    }
+
+    /// Get the audio filters of the queue, changing them affects the playing track immediately.
+    pub fn filters(&self) -> Filters {
+        self.inner.lock().filters.clone()
+    }
+
+    /// Is the loudness of tracks normalised?
+    pub fn normalising(&self) -> bool {
+        self.inner.lock().loudness_target.is_some()
+    }
+
+    /// Get the normalisation gain of the current track in decibels, once its loudness is known.
+    pub fn track_gain(&self) -> Option<f64> {
+        let inner = self.inner.lock();
+        inner.queued_tracks.front()?.gain_db(inner.loudness_target)
+    }
+
     /// Pause the track. It can be resumed later.
     pub fn pause(&self) -> TrackResult<()> {
         let inner = self.inner.lock();
 
-        if let Some(handle) = inner.queued_tracks.front() {
+        if let Some(handle) = inner.queued_tracks.front().and_then(Queued::handle) {
             handle.pause()
         } else {
             Ok(())
@@ -212,7 +1037,7 @@ impl TrackQueue {
     pub fn resume(&self) -> TrackResult<()> {
         let inner = self.inner.lock();
 
-        if let Some(handle) = inner.queued_tracks.front() {
+        if let Some(handle) = inner.queued_tracks.front().and_then(Queued::handle) {
             handle.play()
         } else {
             Ok(())
@@ -243,28 +1068,142 @@ impl TrackQueue {
         }
     }
 
-    /// Try to skip up to `n` tracks.
-    #[allow(unused)]
-    pub fn skip(&self, mut n: usize) -> TrackResult<()> {
-        let inner = self.inner.lock();
+    /// Skip up to `n` tracks, starting with the currently playing one.
+    ///
+    /// Skipped tracks are stored in the history. Returns the number of tracks which were skipped.
+    pub async fn skip(&self, n: usize) -> usize {
+        let skipped = self.inner.lock().advance(n);
+        self.play_front().await;
 
-        while n > 0 && !inner.queued_tracks.is_empty() {
-            inner.stop_current()?;
-            n -= 1;
+        skipped
+    }
+
+    /// Vote to skip `track` as `user`, which only counts while it is the current track.
+    ///
+    /// A new vote is started if the current track changed since the last one. Votes of users who
+    /// aren't among the `listeners` anymore don't count. Once `required` votes are reached, the
+    /// track is skipped right away, so a vote can only pass once. Returns `None` if `track` is not
+    /// playing anymore.
+    pub async fn vote_skip(
+        &self,
+        track: &TrackHandle,
+        user: UserId,
+        listeners: &HashSet<UserId>,
+        required: usize,
+    ) -> Option<Ballot> {
+        let ballot = {
+            let mut inner = self.inner.lock();
+            if !is_current_locked(&inner, track) {
+                return None;
+            }
+
+            let vote = match &mut inner.skip_vote {
+                Some(vote) if vote.track.uuid() == track.uuid() => vote,
+                vote => vote.insert(SkipVote {
+                    track: track.clone(),
+                    voters: HashSet::new(),
+                    message: None,
+                }),
+            };
+            vote.voters.retain(|voter| listeners.contains(voter));
+            vote.voters.insert(user);
+
+            let votes = vote.voters.len();
+            if votes >= required {
+                inner.advance(1);
+                Ballot::Passed
+            } else {
+                Ballot::Counted {
+                    votes,
+                    message: vote.message,
+                }
+            }
+        };
+
+        if ballot == Ballot::Passed {
+            self.play_front().await;
         }
-        Ok(())
+
+        Some(ballot)
+    }
+
+    /// Remember the message showing the vote on skipping `track`.
+    pub fn set_vote_message(&self, track: &TrackHandle, message: (ChannelId, MessageId)) {
+        let mut inner = self.inner.lock();
+
+        if let Some(vote) = inner
+            .skip_vote
+            .as_mut()
+            .filter(|vote| vote.track.uuid() == track.uuid())
+        {
+            vote.message = Some(message);
+        }
+    }
+
+    /// Take back the vote of `user`, e.g. because they left the voice channel.
+    ///
+    /// Returns the remaining number of votes with the message showing the vote, if `user` had
+    /// voted.
+    pub fn retract_vote(&self, user: UserId) -> Option<(usize, Option<(ChannelId, MessageId)>)> {
+        let mut inner = self.inner.lock();
+        let vote = inner.skip_vote.as_mut()?;
+
+        if !vote.voters.remove(&user) {
+            return None;
+        }
+
+        Some((vote.voters.len(), vote.message))
+    }
+
+    /// Get the message of the now playing panel, if it has been sent.
+    pub fn panel(&self) -> Option<(ChannelId, MessageId)> {
+        self.inner.lock().panel
+    }
+
+    /// Remember the message of the now playing panel, or forget it with `None`.
+    pub fn set_panel(&self, panel: Option<(ChannelId, MessageId)>) {
+        self.inner.lock().panel = panel;
+    }
+
+    /// Check whether the panel shows that nothing is playing.
+    pub fn panel_idle(&self) -> bool {
+        self.inner.lock().panel_idle
+    }
+
+    /// Remember whether the panel shows that nothing is playing.
+    pub fn set_panel_idle(&self, idle: bool) {
+        self.inner.lock().panel_idle = idle;
+    }
+
+    /// Get the guild the queue belongs to.
+    pub fn guild_id(&self) -> GuildId {
+        self.inner.lock().guild_id
+    }
+
+    /// Find the index of the first queued track, after the currently playing one, which satisfies
+    /// `pred`.
+    pub fn find<P>(&self, mut pred: P) -> Option<usize>
+    where
+        P: FnMut(&TrackUserData) -> bool,
+    {
+        let inner = self.inner.lock();
+
+        inner
+            .queued_tracks
+            .iter()
+            .skip(1)
+            .position(|track| pred(&track.data))
+            .map(|i| i + 1)
     }
 
     /// Get the contents of the current queue.
-    #[allow(unused)]
-    pub fn current_queue(&self) -> Vec<TrackHandle> {
+    pub fn current_queue(&self) -> Vec<Arc<TrackUserData>> {
         let inner = self.inner.lock();
 
-        inner.queued_tracks.iter().map(Queued::handle).collect()
+        inner.queued_tracks.iter().map(Queued::data).collect()
     }
 
     /// Get the track history.
-    #[allow(unused)]
     pub fn history(&self) -> Vec<TrackUserData> {
         let inner = self.inner.lock();
 
@@ -272,13 +1211,209 @@ impl TrackQueue {
     }
 
     /// Get the metadata of a previously played track.
-    #[allow(unused)]
     pub fn previous(&self, n: usize) -> Option<TrackUserData> {
         let inner = self.inner.lock();
 
         inner.history.peek(n).cloned()
     }
 
+    /// Play the most recently finished track again right away.
+    ///
+    /// The current track is moved back to the second position and starts from the beginning when
+    /// its turn comes. Returns the track which is now playing.
+    pub async fn back(&self) -> Option<TrackUserData> {
+        let data = {
+            let mut inner = self.inner.lock();
+
+            let data = inner.history.remove()?;
+            if let Some(current) = inner.queued_tracks.front_mut() {
+                current.reset();
+            }
+            inner.queued_tracks.push_front(Queued::new(data.clone()));
+
+            data
+        };
+
+        self.play_front().await;
+
+        Some(data)
+    }
+
+    /// Take a snapshot of the queue and the playback position of the current track.
+    pub async fn snapshot(&self) -> QueueSnapshot {
+        let (mut snapshot, current) = {
+            let inner = self.inner.lock();
+
+            let snapshot = QueueSnapshot {
+                tracks: inner
+                    .queued_tracks
+                    .iter()
+                    .map(|track| TrackSnapshot {
+                        data: TrackUserData::clone(&track.data),
+                        volume: track.volume,
+                        loudness: track.loudness,
+                    })
+                    .collect(),
+                position: None,
+                history: inner.history.list(),
+                loop_mode: inner.loop_mode,
+                panel: inner.panel,
+                filters: inner.filters.list(),
+            };
+
+            (
+                snapshot,
+                inner.queued_tracks.front().and_then(Queued::handle),
+            )
+        };
+
+        if let Some(handle) = current {
+            snapshot.position = handle.get_info().await.ok().map(|info| info.position);
+        }
+
+        snapshot
+    }
+
+    /// Get the member the bot follows between voice channels, if any.
+    pub fn following(&self) -> Option<UserId> {
+        self.inner.lock().following
+    }
+
+    /// Follow `user` between voice channels, or stop following with `None`.
+    pub fn set_following(&self, user: Option<UserId>) {
+        self.inner.lock().following = user;
+    }
+
+    /// Remember the position of the current track, in case the voice connection is lost.
+    pub async fn track_position(&self) {
+        let Some((handle, data)) = ({
+            let inner = self.inner.lock();
+            if inner.reconnecting {
+                return;
+            }
+            inner
+                .queued_tracks
+                .front()
+                .and_then(|track| Some((track.handle()?, track.data())))
+        }) else {
+            return;
+        };
+
+        if let Ok(info) = handle.get_info().await {
+            let mut inner = self.inner.lock();
+            // Tracks keep playing into the void while disconnected, those positions are useless.
+            if !inner.reconnecting {
+                inner.last_position = Some((data, info.position));
+            }
+        }
+    }
+
+    /// Stop tracking the position, because the voice connection was lost.
+    pub fn lost_connection(&self) {
+        self.inner.lock().reconnecting = true;
+    }
+
+    /// Continue the current track where the listeners last heard it, once the voice connection
+    /// is back.
+    ///
+    /// A track which broke in the meantime is built again from its recipe.
+    pub async fn recover(&self) {
+        let (current, position) = {
+            let mut inner = self.inner.lock();
+            inner.reconnecting = false;
+
+            let Some(front) = inner.queued_tracks.front() else {
+                return;
+            };
+            let position = inner
+                .last_position
+                .as_ref()
+                .filter(|(data, _)| Arc::ptr_eq(data, &front.data))
+                .map(|(_, position)| *position);
+            (front.handle(), position)
+        };
+
+        let alive = match &current {
+            Some(handle) => handle
+                .get_info()
+                .await
+                .is_ok_and(|info| !info.playing.is_done()),
+            None => false,
+        };
+        if !alive {
+            if let Some(front) = self.inner.lock().queued_tracks.front_mut() {
+                front.reset();
+            }
+            self.play_front().await;
+        }
+
+        if let (Some(position), Some(handle)) = (position, self.current()) {
+            drop(handle.seek(position));
+        }
+    }
+
+    /// Stop every track and move it into the history, because the bot left the voice channel.
+    pub fn archive(&self) {
+        let mut inner = self.inner.lock();
+
+        let len = inner.queued_tracks.len();
+        inner.remove_front(len, false);
+    }
+
+    /// Play into `call` from now on, e.g. after joining again.
+    ///
+    /// Tracks registered with the driver of another call can't play anymore, so they are built
+    /// again and the first one starts from the beginning.
+    pub fn attach(&self, call: &Arc<tokio::sync::Mutex<Call>>, driver: &mut Driver) {
+        {
+            let mut inner = self.inner.lock();
+            if inner
+                .call
+                .upgrade()
+                .is_some_and(|current| Arc::ptr_eq(&current, call))
+            {
+                return;
+            }
+
+            inner.call = Arc::downgrade(call);
+            for track in &mut inner.queued_tracks {
+                track.reset();
+            }
+        }
+
+        self.play_front_with(driver);
+    }
+
+    /// Fill the queue with the contents of a saved `snapshot` and continue playing from the saved
+    /// position.
+    pub fn restore(&self, snapshot: QueueSnapshot, driver: &mut Driver) {
+        {
+            let mut inner = self.inner.lock();
+
+            for data in snapshot.history {
+                inner.history.add(data);
+            }
+            inner.loop_mode = snapshot.loop_mode;
+            inner.panel = snapshot.panel;
+            for filter in snapshot.filters {
+                inner.filters.enable(filter);
+            }
+            inner
+                .queued_tracks
+                .extend(snapshot.tracks.into_iter().map(|track| Queued {
+                    volume: track.volume,
+                    loudness: track.loudness,
+                    ..Queued::new(track.data)
+                }));
+        }
+
+        self.play_front_with(driver);
+
+        if let (Some(position), Some(handle)) = (snapshot.position, self.current()) {
+            drop(handle.seek(position));
+        }
+    }
+
     /// Shuffle the queue, leaving the first (currently playing) track untouched.
     pub fn shuffle(&self) {
         let mut inner = self.inner.lock();
@@ -292,6 +1427,56 @@ impl TrackQueue {
 }
 
 impl TrackQueueCore {
+    /// Remove the first `n` tracks from the queue and store them in the history.
+    ///
+    /// The next track has to be started with `TrackQueue::play_front`. When the whole queue loops,
+    /// the removed tracks are queued again at the back, so skipping doesn't drop them out of the
+    /// rotation. Returns the number of tracks which were removed.
+    pub fn advance(&mut self, n: usize) -> usize {
+        let requeue = self.loop_mode == LoopMode::Queue;
+        self.remove_front(n, requeue)
+    }
+
+    /// Remove the first `n` tracks like `advance`, queueing them again at the back if `requeue`.
+    fn remove_front(&mut self, n: usize, requeue: bool) -> usize {
+        // Requeued tracks come around again, they are not removed twice.
+        let n = n.min(self.queued_tracks.len());
+        let mut removed = 0;
+
+        while removed < n
+            && let Some(track) = self.queued_tracks.pop_front()
+        {
+            if requeue {
+                self.queued_tracks.push_back(track.fresh());
+            }
+            // The track might have already ended, in which case this fails, which is fine.
+            // Otherwise the first track was playing and is about to end.
+            if track.stop().is_ok()
+                && removed == 0
+                && let Some(handle) = track.handle()
+            {
+                self.skipped.push(handle);
+            }
+            self.history.add(Arc::unwrap_or_clone(track.data));
+            removed += 1;
+        }
+
+        // Votes are only ever about the current track.
+        if removed > 0 {
+            self.skip_vote = None;
+        }
+
+        removed
+    }
+
+    /// Forget that the track of `handle` was skipped, returning whether it was.
+    pub fn take_skipped(&mut self, handle: &TrackHandle) -> bool {
+        let before = self.skipped.len();
+        self.skipped
+            .retain(|skipped| skipped.uuid() != handle.uuid());
+        self.skipped.len() != before
+    }
+
     fn stop_current(&self) -> TrackResult<()> {
         if let Some(handle) = self.queued_tracks.front() {
             handle.stop()
@@ -301,6 +1486,28 @@ impl TrackQueueCore {
     }
 }
 
+/// Is the track of `handle` the current one?
+fn is_current_locked(inner: &TrackQueueCore, handle: &TrackHandle) -> bool {
+    inner
+        .queued_tracks
+        .front()
+        .and_then(Queued::handle)
+        .is_some_and(|h| h.uuid() == handle.uuid())
+}
+
+/// Call `step` with the progress of a fade lasting `length`, going up to `1.0`, every
+/// `FADE_STEP`. The fade ends early once `step` returns `false`.
+async fn fade(length: Duration, mut step: impl FnMut(f32) -> bool) {
+    let steps = (length.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+
+    for i in 1..=steps {
+        tokio::time::sleep(FADE_STEP).await;
+        if !step(i as f32 / steps as f32) {
+            return;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,4 +1526,56 @@ mod tests {
 
         assert_ne!(queue, snd);
     }
+
+    #[test]
+    fn skipping_in_queue_loop() {
+        let track = |title: &str| {
+            Queued::new(TrackUserData::new(
+                SourceKind::Youtube,
+                title.into(),
+                format!("https://yt/{title}"),
+                None,
+            ))
+        };
+        let titles = |core: &TrackQueueCore| {
+            core.queued_tracks
+                .iter()
+                .map(|track| track.data.title.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut core = TrackQueueCore {
+            loop_mode: LoopMode::Queue,
+            ..Default::default()
+        };
+        core.queued_tracks.extend(["a", "b", "c"].map(track));
+
+        assert_eq!(core.advance(1), 1);
+        assert_eq!(titles(&core), ["b", "c", "a"]);
+        assert_eq!(core.advance(5), 3);
+        assert_eq!(titles(&core), ["b", "c", "a"]);
+
+        core.loop_mode = LoopMode::Off;
+        assert_eq!(core.advance(2), 2);
+        assert_eq!(titles(&core), ["a"]);
+    }
+
+    #[test]
+    fn transitions() {
+        assert_eq!(Transition::parse("off"), Some(Transition::Off));
+        assert_eq!(Transition::parse("Gapless"), Some(Transition::Gapless));
+        assert_eq!(Transition::parse("5s"), Some(Transition::Crossfade(5)));
+        assert_eq!(Transition::parse("12"), Some(Transition::Crossfade(12)));
+        assert_eq!(Transition::parse("0"), None);
+        assert_eq!(Transition::parse("13s"), None);
+        assert_eq!(Transition::parse("smooth"), None);
+
+        assert_eq!(
+            Transition::Crossfade(3).crossfade(),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(Transition::Gapless.crossfade(), None);
+        assert_eq!(Transition::Gapless.overlap(), Some(Duration::ZERO));
+        assert_eq!(Transition::Off.overlap(), None);
+    }
 }
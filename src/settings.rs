@@ -0,0 +1,342 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use parking_lot::Mutex;
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId};
+
+use crate::{
+    Result_,
+    config::Config,
+    error::ScumboError,
+    queue::{LoopMode, Transition},
+};
+
+/// The highest volume a guild can choose, in percent.
+pub const MAX_VOLUME: u16 = 200;
+
+/// A single setting which can be changed with the `config` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Setting {
+    #[name = "prefix"]
+    Prefix,
+    #[name = "volume"]
+    Volume,
+    #[name = "history_capacity"]
+    HistoryCapacity,
+    #[name = "announce_channel"]
+    AnnounceChannel,
+    #[name = "max_queue_length"]
+    MaxQueueLength,
+    #[name = "loop_mode"]
+    LoopMode,
+    #[name = "dj_role"]
+    DjRole,
+    #[name = "vote_skip_percent"]
+    VoteSkipPercent,
+    #[name = "normalise"]
+    Normalise,
+    #[name = "transition"]
+    Transition,
+    #[name = "stay_connected"]
+    StayConnected,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 11] = [
+        Setting::Prefix,
+        Setting::Volume,
+        Setting::HistoryCapacity,
+        Setting::AnnounceChannel,
+        Setting::MaxQueueLength,
+        Setting::LoopMode,
+        Setting::DjRole,
+        Setting::VoteSkipPercent,
+        Setting::Normalise,
+        Setting::Transition,
+        Setting::StayConnected,
+    ];
+}
+
+/// The settings of a single guild.
+///
+/// Unset values fall back to the global `Config` or a built-in default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub prefix: Option<String>,
+    /// Volume new tracks start at, in percent.
+    pub volume: Option<u16>,
+    pub history_capacity: Option<usize>,
+    /// Where the "Now playing" messages are sent, instead of the channel the bot was summoned from.
+    pub announce_channel: Option<ChannelId>,
+    pub max_queue_length: Option<usize>,
+    /// Loop mode of a newly created queue.
+    pub loop_mode: LoopMode,
+    /// Members with this role may change the whole queue, besides those with Manage Server.
+    pub dj_role: Option<RoleId>,
+    /// Percentage of the listeners needed to skip someone else's track.
+    pub vote_skip_percent: Option<u8>,
+    /// Whether the loudness of tracks is normalised to the target of the `Config`.
+    pub normalise: bool,
+    /// How one track turns into the next.
+    pub transition: Transition,
+    /// Whether the bot stays in voice around the clock, even when alone or idle.
+    pub stay_connected: bool,
+}
+
+impl GuildSettings {
+    /// Get the prefix, falling back to the global one.
+    pub fn prefix(&self, config: &Config) -> String {
+        self.prefix.clone().unwrap_or_else(|| config.prefix.clone())
+    }
+
+    /// Get the volume new tracks start at, where `1.0` is the unchanged volume.
+    pub fn volume(&self) -> f32 {
+        f32::from(self.volume.unwrap_or(100)) / 100.0
+    }
+
+    /// Get the percentage of listeners needed to skip someone else's track.
+    pub fn vote_skip_percent(&self) -> u8 {
+        self.vote_skip_percent.unwrap_or(50)
+    }
+
+    /// Get the loudness tracks are normalised to in LUFS, if normalisation is on.
+    pub fn loudness_target(&self, config: &Config) -> Option<f64> {
+        self.normalise.then_some(f64::from(config.loudness_target))
+    }
+
+    /// Get the history capacity, falling back to the global one.
+    pub fn history_capacity(&self, config: &Config) -> usize {
+        self.history_capacity.unwrap_or(config.history_capacity)
+    }
+
+    /// Show the current value of a setting.
+    pub fn get(&self, setting: Setting, config: &Config) -> String {
+        match setting {
+            Setting::Prefix => format!("`{}`", self.prefix(config)),
+            Setting::Volume => format!("{}%", self.volume.unwrap_or(100)),
+            Setting::HistoryCapacity => self.history_capacity(config).to_string(),
+            Setting::AnnounceChannel => match self.announce_channel {
+                Some(channel) => format!("<#{channel}>"),
+                None => "the channel the bot was summoned from".into(),
+            },
+            Setting::MaxQueueLength => match self.max_queue_length {
+                Some(length) => length.to_string(),
+                None => "unlimited".into(),
+            },
+            Setting::LoopMode => format!("`{}`", self.loop_mode.name()),
+            Setting::DjRole => match self.dj_role {
+                Some(role) => format!("<@&{role}>"),
+                None => "not set, only members with Manage Server are DJs".into(),
+            },
+            Setting::VoteSkipPercent => format!("{}%", self.vote_skip_percent()),
+            Setting::Normalise => match self.normalise {
+                true => format!("on, to {} LUFS", config.loudness_target),
+                false => "off".into(),
+            },
+            Setting::Transition => format!("`{}`", self.transition.name()),
+            Setting::StayConnected => match self.stay_connected {
+                true => "on, the bot stays in voice 24/7".into(),
+                false => "off".into(),
+            },
+        }
+    }
+
+    /// Change a setting from its textual value.
+    pub fn set(&mut self, setting: Setting, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let number = |max: usize| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=max).contains(n))
+                .ok_or_else(|| format!("`{value}` is not a number between 1 and {max}."))
+        };
+        let switch = || match value.to_lowercase().as_str() {
+            "on" | "true" | "yes" => Ok(true),
+            "off" | "false" | "no" => Ok(false),
+            _ => Err(format!("`{value}` is neither `on` nor `off`.")),
+        };
+
+        match setting {
+            Setting::Prefix => {
+                if value.is_empty() || value.len() > 10 || value.contains(char::is_whitespace) {
+                    return Err("The prefix has to be 1 to 10 characters, without spaces.".into());
+                }
+                self.prefix = Some(value.into());
+            }
+            Setting::Volume => {
+                self.volume = value
+                    .trim_end_matches('%')
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|&volume| volume <= MAX_VOLUME)
+                    .map(Some)
+                    .ok_or_else(|| format!("The volume has to be between 0 and {MAX_VOLUME}%."))?
+            }
+            Setting::HistoryCapacity => self.history_capacity = Some(number(1000)?),
+            Setting::AnnounceChannel => {
+                let id = value
+                    .trim_start_matches("<#")
+                    .trim_end_matches('>')
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&id| id != 0)
+                    .ok_or_else(|| format!("`{value}` is not a channel."))?;
+                self.announce_channel = Some(ChannelId::new(id));
+            }
+            Setting::MaxQueueLength => self.max_queue_length = Some(number(10_000)?),
+            Setting::LoopMode => {
+                self.loop_mode = LoopMode::from_name(value).ok_or_else(|| {
+                    format!("`{value}` is not a loop mode, use `off`, `track` or `queue`.")
+                })?
+            }
+            Setting::DjRole => {
+                let id = value
+                    .trim_start_matches("<@&")
+                    .trim_end_matches('>')
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&id| id != 0)
+                    .ok_or_else(|| format!("`{value}` is not a role."))?;
+                self.dj_role = Some(RoleId::new(id));
+            }
+            Setting::VoteSkipPercent => {
+                self.vote_skip_percent = value
+                    .trim_end_matches('%')
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|percent| (1..=100).contains(percent))
+                    .map(Some)
+                    .ok_or("The percentage has to be between 1 and 100%.")?
+            }
+            Setting::Normalise => self.normalise = switch()?,
+            Setting::Transition => {
+                self.transition = Transition::parse(value).ok_or_else(|| {
+                    format!(
+                        "`{value}` is not a transition, use `off`, `gapless` or the seconds of a \
+                         crossfade, up to {}.",
+                        Transition::MAX_CROSSFADE
+                    )
+                })?
+            }
+            Setting::StayConnected => self.stay_connected = switch()?,
+        }
+
+        Ok(())
+    }
+
+    /// Set a setting back to its default.
+    pub fn reset(&mut self, setting: Setting) {
+        let default = Self::default();
+        match setting {
+            Setting::Prefix => self.prefix = default.prefix,
+            Setting::Volume => self.volume = default.volume,
+            Setting::HistoryCapacity => self.history_capacity = default.history_capacity,
+            Setting::AnnounceChannel => self.announce_channel = default.announce_channel,
+            Setting::MaxQueueLength => self.max_queue_length = default.max_queue_length,
+            Setting::LoopMode => self.loop_mode = default.loop_mode,
+            Setting::DjRole => self.dj_role = default.dj_role,
+            Setting::VoteSkipPercent => self.vote_skip_percent = default.vote_skip_percent,
+            Setting::Normalise => self.normalise = default.normalise,
+            Setting::Transition => self.transition = default.transition,
+            Setting::StayConnected => self.stay_connected = default.stay_connected,
+        }
+    }
+}
+
+/// The settings of all guilds, kept in a single JSON file which is rewritten on every change.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    path: PathBuf,
+    guilds: Arc<Mutex<HashMap<GuildId, GuildSettings>>>,
+}
+
+impl Settings {
+    /// Load the settings stored at `path`, starting with the defaults if the file doesn't exist.
+    pub fn load(path: impl Into<PathBuf>) -> Result_<Self> {
+        let path = path.into();
+        let guilds = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            path,
+            guilds: Arc::new(Mutex::new(guilds)),
+        })
+    }
+
+    /// Get the settings of a guild.
+    pub fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.guilds
+            .lock()
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Change the settings of a guild with `f` and save them, unless `f` fails.
+    ///
+    /// Returns the new settings.
+    pub fn update(
+        &self,
+        guild_id: GuildId,
+        f: impl FnOnce(&mut GuildSettings) -> Result<(), String>,
+    ) -> Result_<GuildSettings> {
+        let mut guilds = self.guilds.lock();
+        let mut settings = guilds.get(&guild_id).cloned().unwrap_or_default();
+        f(&mut settings).map_err(ScumboError::InvalidArgument)?;
+
+        if settings == GuildSettings::default() {
+            guilds.remove(&guild_id);
+        } else {
+            guilds.insert(guild_id, settings.clone());
+        }
+
+        crate::persist::write_atomic(&self.path, &serde_json::to_vec(&*guilds)?)?;
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_reset() {
+        let config = Config::default();
+        let mut settings = GuildSettings::default();
+
+        settings.set(Setting::Volume, "50%").unwrap();
+        settings.set(Setting::AnnounceChannel, "<#123>").unwrap();
+        settings.set(Setting::LoopMode, "queue").unwrap();
+        settings.set(Setting::DjRole, "<@&7>").unwrap();
+        settings.set(Setting::Normalise, "on").unwrap();
+        settings.set(Setting::Transition, "4s").unwrap();
+        settings.set(Setting::StayConnected, "yes").unwrap();
+        assert_eq!(settings.volume(), 0.5);
+        assert_eq!(settings.get(Setting::AnnounceChannel, &config), "<#123>");
+        assert_eq!(settings.loop_mode, LoopMode::Queue);
+        assert_eq!(settings.dj_role, Some(RoleId::new(7)));
+        assert!(settings.normalise);
+        assert_eq!(settings.transition, Transition::Crossfade(4));
+        assert!(settings.stay_connected);
+
+        assert!(settings.set(Setting::Volume, "300").is_err());
+        assert!(settings.set(Setting::Prefix, "two words").is_err());
+        assert!(settings.set(Setting::HistoryCapacity, "0").is_err());
+        assert!(settings.set(Setting::LoopMode, "forever").is_err());
+        assert!(settings.set(Setting::Normalise, "maybe").is_err());
+        assert!(settings.set(Setting::Transition, "60").is_err());
+
+        assert_eq!(settings.history_capacity(&config), config.history_capacity);
+        for setting in Setting::ALL {
+            settings.reset(setting);
+        }
+        assert_eq!(settings, GuildSettings::default());
+    }
+}
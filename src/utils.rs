@@ -1,7 +1,7 @@
 use poise::CreateReply;
-use serenity::all::{CreateEmbed, Http};
+use serenity::all::CreateEmbed;
 
-use crate::{Context, Result_};
+use crate::{Context, Result_, error::ScumboError, queue::TrackQueue};
 
 /// This is an auxiliary function which does the "joining" to a voice channel. It is here, because
 /// both `join` and `play` commands are used to join a voice channel.
@@ -9,101 +9,205 @@ pub async fn join_voice(
     ctx: Context<'_>,
     voice_channel: Option<serenity::model::channel::GuildChannel>,
 ) -> Result_<()> {
-    let guild_id = ctx.guild_id().expect("User should be in a guild.");
+    let guild_id = guild_id(ctx)?;
 
     // Check if the provided channel is a voice channel.
     if let Some(vc) = voice_channel.as_ref()
         && !matches!(vc.kind, serenity::all::ChannelType::Voice)
     {
-        ctx.send(reply("Error", "_That_ is not a **voice** channel."))
-            .await?;
-        return Ok(());
+        return Err(ScumboError::NotVoiceChannel);
     }
 
     // Use the provided voice channel or get the channel the user is in.
     let channel_id = voice_channel.map(|channel| channel.id).or_else(|| {
-        ctx.guild()
-            .unwrap()
+        ctx.guild()?
             .voice_states
             .get(&ctx.author().id)
             .and_then(|voice_state| voice_state.channel_id)
     });
+    let connect_to = channel_id.ok_or(ScumboError::UserNotInVoice)?;
 
-    let connect_to = match channel_id {
-        Some(ch) => ch,
-        None => {
-            ctx.send(reply("Error", "You are not in a voice channel, good sir!"))
-                .await?;
+    let songbird_manager = voice_manager(ctx).await?;
 
-            return Ok(());
+    let call = songbird_manager.join(guild_id, connect_to).await?;
+    let mut driver = call.lock().await;
+    let settings = ctx.data().settings.get(guild_id);
+    let existing = ctx.data().qs.lock().get(&guild_id).cloned();
+    let queue = match existing {
+        // Joining again, e.g. to move to another channel, keeps the queue playing.
+        Some(queue) => {
+            queue.apply_settings(&settings, &ctx.data().config);
+            queue.attach(&call, &mut driver);
+            queue
+        }
+        None => {
+            let queue = super::queue::TrackQueue::new(
+                &settings,
+                &ctx.data().config,
+                guild_id,
+                &call,
+                ctx.data().client.clone(),
+                ctx.data().play_log.clone(),
+            );
+            // Continue where the guild left off before the bot restarted or left.
+            if let Some(snapshot) = ctx.data().restored.lock().remove(&guild_id) {
+                queue.restore(snapshot, &mut driver);
+            }
+            ctx.data().qs.lock().insert(guild_id, queue.clone());
+            queue
         }
     };
 
-    let songbird_manager = songbird::get(ctx.serenity_context())
-        .await
-        .expect("Created at initialization.")
-        .clone();
-
-    let connection_result = songbird_manager.join(guild_id, connect_to).await;
-    match connection_result {
-        Ok(driver) => {
-            let mut driver = driver.lock().await;
-            ctx.data()
-                .qs
-                .lock()
-                .insert(guild_id, super::queue::TrackQueue::new(50));
-            // This is abhorrent.
-            let token = dotenv::var("DISCORD_TOKEN").expect("Should be loaded.");
-            let http = Http::new(&token);
-            driver.add_global_event(
-                songbird::TrackEvent::Play.into(),
-                crate::handlers::ResumeHandler((ctx.channel_id(), http)),
-            );
-            driver.add_global_event(
-                songbird::TrackEvent::Error.into(),
-                crate::handlers::TrackErrorHandler,
-            );
+    // The call is kept between joins, so the handlers of the last join are still there.
+    driver.remove_all_global_events();
+    let http = ctx.serenity_context().http.clone();
+    driver.add_global_event(
+        songbird::TrackEvent::Play.into(),
+        crate::handlers::ResumeHandler {
+            guild_id,
+            channel_id: ctx.channel_id(),
+            http: http.clone(),
+            settings: ctx.data().settings.clone(),
+            queue: queue.clone(),
+            inactivity: ctx.data().inactivity.clone(),
+        },
+    );
+    driver.add_global_event(
+        songbird::TrackEvent::End.into(),
+        crate::handlers::IdleHandler {
+            guild_id,
+            inactivity: ctx.data().inactivity.clone(),
+        },
+    );
+    driver.add_global_event(
+        songbird::Event::Periodic(crate::panel::REFRESH_INTERVAL, None),
+        crate::handlers::PanelRefresher {
+            http,
+            queue: queue.clone(),
+        },
+    );
+    driver.add_global_event(
+        songbird::TrackEvent::Error.into(),
+        crate::handlers::TrackErrorHandler,
+    );
+    let connection = crate::handlers::ConnectionHandler {
+        guild_id,
+        songbird: songbird_manager.clone(),
+        http: ctx.serenity_context().http.clone(),
+        settings: ctx.data().settings.clone(),
+        queue: queue.clone(),
+    };
+    driver.add_global_event(
+        songbird::CoreEvent::DriverReconnect.into(),
+        connection.clone(),
+    );
+    driver.add_global_event(songbird::CoreEvent::DriverDisconnect.into(), connection);
+    driver.add_global_event(
+        songbird::Event::Periodic(crate::handlers::POSITION_INTERVAL, None),
+        crate::handlers::PositionTracker { queue },
+    );
+    drop(driver);
 
-            ctx.send(reply(
-                "Info",
-                format!(
-                    "Joined voice channel: {}",
-                    ctx.guild()
-                        .expect("Should be in a guild")
-                        .channels
-                        .get(&connect_to)
-                        .expect("Should exist."),
-                ),
-            ))
-            .await?;
-        }
-        Err(e) => {
-            ctx.reply(format!("Could not join the voice channel because: {e}"))
-                .await?;
-        }
-    }
+    // Nothing plays yet, and the bot might have been summoned into an empty channel.
+    let inactivity = &ctx.data().inactivity;
+    inactivity.restart(guild_id, crate::inactivity::Reason::Idle);
+    inactivity.voice_state_changed(guild_id);
+
+    ctx.send(reply(
+        "Info",
+        format!("Joined voice channel: <#{connect_to}>"),
+    ))
+    .await?;
 
     Ok(())
 }
 
+/// Forget the queue of a guild after the bot left its voice channel, for whatever reason.
+///
+/// The tracks are stopped and archived into the history, which is saved and comes back when the
+/// bot joins again.
+pub async fn clean_up(data: &crate::State, guild_id: serenity::all::GuildId) {
+    for reason in [
+        crate::inactivity::Reason::Alone,
+        crate::inactivity::Reason::Idle,
+    ] {
+        data.inactivity.cancel(guild_id, reason);
+    }
+
+    let Some(queue) = data.qs.lock().remove(&guild_id) else {
+        return;
+    };
+    queue.archive();
+
+    let snapshot = queue.snapshot().await;
+    if let Err(e) = data.store.save(guild_id, &snapshot) {
+        println!("could not save queue of guild {guild_id}: {e}");
+    }
+    data.restored.lock().insert(guild_id, snapshot);
+}
+
 /// Used to check if the bot is in a voice channel.
 pub async fn in_voice(ctx: Context<'_>) -> Result_<(std::sync::Arc<songbird::Songbird>, bool)> {
-    let guild_id = ctx.guild_id().expect("Should be in a guild.");
+    let guild_id = guild_id(ctx)?;
+    let songbird_manager = voice_manager(ctx).await?;
+    let has_handler = songbird_manager.get(guild_id).is_some();
 
-    // Get the voice manager.
-    let songbird_manager = songbird::get(ctx.serenity_context())
+    Ok((songbird_manager, has_handler))
+}
+
+/// Get the guild the command was used in, commands are `guild_only` so this only fails if
+/// Discord sends something unexpected.
+pub fn guild_id(ctx: Context<'_>) -> Result_<serenity::all::GuildId> {
+    ctx.guild_id().ok_or(ScumboError::NotInGuild)
+}
+
+/// Get the voice manager, which is registered with the client at startup.
+pub async fn voice_manager(ctx: Context<'_>) -> Result_<std::sync::Arc<songbird::Songbird>> {
+    songbird::get(ctx.serenity_context())
         .await
-        .expect("Should be initilaized")
-        .clone();
+        .ok_or(ScumboError::VoiceUnavailable)
+}
 
-    Ok((
-        songbird_manager.clone(),
-        songbird_manager.get(guild_id).is_some(),
-    ))
+/// Get the queue of the guild, which is created when the bot joins one of its voice channels.
+pub fn guild_queue(ctx: Context<'_>) -> Result_<TrackQueue> {
+    let guild_id = guild_id(ctx)?;
+
+    ctx.data()
+        .qs
+        .lock()
+        .get(&guild_id)
+        .cloned()
+        .ok_or(ScumboError::QueueMissing)
+}
+
+/// Get the queue of the guild, failing if the bot is not in one of its voice channels.
+pub async fn voice_queue(ctx: Context<'_>) -> Result_<TrackQueue> {
+    let (_, has_handler) = in_voice(ctx).await?;
+    if !has_handler {
+        return Err(ScumboError::NotInVoice);
+    }
+
+    guild_queue(ctx)
+}
+
+/// Get the queue of the guild, joining the author's voice channel first if the bot is in none.
+pub async fn join_queue(ctx: Context<'_>) -> Result_<TrackQueue> {
+    let (_, has_handler) = in_voice(ctx).await?;
+    if !has_handler {
+        join_voice(ctx, None).await?;
+    }
+
+    guild_queue(ctx)
 }
 
 /// Custom implementation of pagination based on `poise::builtin::paginate`.
+///
+/// Sends nothing without any pages, callers say that there is nothing to show themselves.
 pub async fn paginate(ctx: Context<'_>, pages: Vec<String>) -> Result<(), serenity::Error> {
+    let Some(first) = pages.first() else {
+        return Ok(());
+    };
+
     // Define some unique identifiers for the navigation buttons
     let ctx_id = ctx.id();
     let prev_button_id = format!("{}prev", ctx_id);
@@ -117,7 +221,7 @@ pub async fn paginate(ctx: Context<'_>, pages: Vec<String>) -> Result<(), sereni
         ]);
 
         CreateReply::default()
-            .embed(serenity::all::CreateEmbed::default().description(&pages[0]))
+            .embed(serenity::all::CreateEmbed::default().description(first))
             .components(vec![components])
     };
 
@@ -161,9 +265,162 @@ pub async fn paginate(ctx: Context<'_>, pages: Vec<String>) -> Result<(), sereni
     Ok(())
 }
 
+/// Parse a single index (`3`) or an inclusive range of indices (`2-5`).
+pub fn parse_range(input: &str) -> Option<std::ops::RangeInclusive<usize>> {
+    let (start, end) = match input.split_once('-') {
+        Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+        None => {
+            let index = input.trim().parse().ok()?;
+            (index, index)
+        }
+    };
+
+    (start <= end).then_some(start..=end)
+}
+
+/// Where to seek to in the current track, as parsed by `parse_seek`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seek {
+    /// To a position from the start of the track.
+    To(std::time::Duration),
+    Forward(std::time::Duration),
+    Backward(std::time::Duration),
+}
+
+impl Seek {
+    /// Get the position to seek to, when the track is at `position`.
+    ///
+    /// Returns `None` if seeking forward would overflow.
+    pub fn target(self, position: std::time::Duration) -> Option<std::time::Duration> {
+        match self {
+            Seek::To(target) => Some(target),
+            Seek::Forward(offset) => position.checked_add(offset),
+            Seek::Backward(offset) => Some(position.saturating_sub(offset)),
+        }
+    }
+}
+
+/// Parse a timestamp like `1:23`, `1:02:03`, `90` or `1m30s`.
+///
+/// With a leading `+` or `-` it is relative to the current position, e.g. `+30s` or `-10s`.
+pub fn parse_seek(input: &str) -> Option<Seek> {
+    let input = input.trim();
+    let (seek, timestamp): (fn(_) -> _, _) = match input.as_bytes().first()? {
+        b'+' => (Seek::Forward, &input[1..]),
+        b'-' => (Seek::Backward, &input[1..]),
+        _ => (Seek::To, input),
+    };
+
+    parse_timestamp(timestamp.trim()).map(seek)
+}
+
+/// Returns `None` for anything which isn't a timestamp, including ones too large to represent.
+fn parse_timestamp(input: &str) -> Option<std::time::Duration> {
+    if input.contains(':') {
+        let parts = input
+            .split(':')
+            .map(|part| part.trim().parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let seconds = match parts[..] {
+            [minutes, seconds] if seconds < 60 => minutes.checked_mul(60)?.checked_add(seconds)?,
+            [hours, minutes, seconds] if minutes < 60 && seconds < 60 => {
+                hours
+                    .checked_mul(3600)?
+                    .checked_add(minutes * 60 + seconds)?
+            }
+            _ => return None,
+        };
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+
+    // Units, e.g. `1h2m3s` or `30s`.
+    let mut seconds = 0u64;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let value = number.parse::<u64>().ok()?.checked_mul(unit)?;
+        seconds = seconds.checked_add(value)?;
+        number.clear();
+    }
+
+    (!input.is_empty() && number.is_empty()).then_some(std::time::Duration::from_secs(seconds))
+}
+
+/// Format a duration as `m:ss`, or `h:mm:ss` if it is an hour or longer.
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
+}
+
 /// Used to quickly create a reply embed.
 pub fn reply(title: impl Into<String>, content: impl Into<String>) -> CreateReply {
     CreateReply::default()
         .embed(CreateEmbed::new().title(title).description(content))
         .reply(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("3"), Some(3..=3));
+        assert_eq!(parse_range("2-5"), Some(2..=5));
+        assert_eq!(parse_range(" 2 - 5 "), Some(2..=5));
+        assert_eq!(parse_range("5-2"), None);
+        assert_eq!(parse_range("two"), None);
+    }
+
+    #[test]
+    fn durations() {
+        use std::time::Duration;
+
+        assert_eq!(format_duration(Duration::from_secs(7)), "0:07");
+        assert_eq!(format_duration(Duration::from_secs(213)), "3:33");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+
+    #[test]
+    fn seeks() {
+        use std::time::Duration;
+
+        let secs = Duration::from_secs;
+        assert_eq!(parse_seek("1:23"), Some(Seek::To(secs(83))));
+        assert_eq!(parse_seek("1:02:03"), Some(Seek::To(secs(3723))));
+        assert_eq!(parse_seek("90"), Some(Seek::To(secs(90))));
+        assert_eq!(parse_seek("1m30s"), Some(Seek::To(secs(90))));
+        assert_eq!(parse_seek("+30s"), Some(Seek::Forward(secs(30))));
+        assert_eq!(parse_seek("-10s"), Some(Seek::Backward(secs(10))));
+        assert_eq!(parse_seek("- 0:10"), Some(Seek::Backward(secs(10))));
+
+        assert_eq!(parse_seek(""), None);
+        assert_eq!(parse_seek("+"), None);
+        assert_eq!(parse_seek("1:75"), None);
+        assert_eq!(parse_seek("1m30"), None);
+        assert_eq!(parse_seek("soon"), None);
+        assert_eq!(parse_seek("99999999999999999h"), None);
+        assert_eq!(parse_seek("9999999999999999999:00"), None);
+        assert_eq!(parse_seek("5124095576030432h1m"), None);
+
+        assert_eq!(Seek::Backward(secs(10)).target(secs(5)), Some(secs(0)));
+        assert_eq!(Seek::Forward(secs(10)).target(secs(5)), Some(secs(15)));
+        assert_eq!(Seek::Forward(secs(u64::MAX)).target(secs(5)), None);
+    }
+}
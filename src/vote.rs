@@ -0,0 +1,248 @@
+use std::{collections::HashSet, time::Duration};
+
+use poise::CreateReply;
+use serenity::all::{
+    ChannelId, ComponentInteractionCollector, CreateActionRow, CreateButton, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Guild, MessageId, UserId,
+};
+use songbird::tracks::TrackHandle;
+
+use crate::{Context, Result_, error::ScumboError, queue::TrackQueue};
+
+/// How long a vote keeps running without anyone voting.
+const VOTE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often a running vote checks whether its track is still playing.
+const TRACK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A vote to skip the currently playing track.
+#[derive(Debug)]
+pub struct SkipVote {
+    /// The track which is voted on, the vote is over once it stops playing.
+    pub track: TrackHandle,
+    pub voters: HashSet<UserId>,
+    /// The message showing the vote, once it has been sent.
+    pub message: Option<(ChannelId, MessageId)>,
+}
+
+/// What happened with a vote to skip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ballot {
+    /// The vote counted, but more are needed. Comes with the message showing the vote.
+    Counted {
+        votes: usize,
+        message: Option<(ChannelId, MessageId)>,
+    },
+    /// Enough listeners voted, the track was skipped.
+    Passed,
+}
+
+/// Get everyone, except for bots, who is in the bot's voice channel.
+pub fn listeners(guild: &Guild, bot_id: UserId) -> HashSet<UserId> {
+    let Some(channel) = guild
+        .voice_states
+        .get(&bot_id)
+        .and_then(|state| state.channel_id)
+    else {
+        return HashSet::new();
+    };
+
+    guild
+        .voice_states
+        .values()
+        .filter(|state| state.channel_id == Some(channel) && state.user_id != bot_id)
+        .filter(|state| !state.member.as_ref().is_some_and(|member| member.user.bot))
+        .map(|state| state.user_id)
+        .collect()
+}
+
+/// How many votes are needed to skip, when `listeners` people are listening.
+pub fn required_votes(listeners: usize, percent: u8) -> usize {
+    (listeners * usize::from(percent)).div_ceil(100).max(1)
+}
+
+/// The embed showing how a vote is going.
+pub fn embed(title: &str, votes: usize, required: usize) -> CreateEmbed {
+    CreateEmbed::new()
+        .title("Vote to skip")
+        .description(format!("Skip {title}?\n\n**{votes}/{required}** votes"))
+}
+
+fn ended(title: &str, outcome: &str) -> CreateEmbed {
+    CreateEmbed::new()
+        .title("Vote to skip")
+        .description(format!("Skip {title}?\n\n{outcome}"))
+}
+
+/// Let the listeners vote on skipping the current track, starting with the author's vote.
+///
+/// If a vote on the current track is already running, the author's vote is added to it instead.
+pub async fn run(ctx: Context<'_>, q: &TrackQueue) -> Result_<()> {
+    let guild_id = super::utils::guild_id(ctx)?;
+    let bot_id = ctx.framework().bot_id;
+    let percent = ctx.data().settings.get(guild_id).vote_skip_percent();
+    let current_listeners = || {
+        ctx.guild()
+            .map(|guild| listeners(&guild, bot_id))
+            .unwrap_or_default()
+    };
+
+    let (Some(track), Some(data)) = (q.current(), q.current_queue().first().cloned()) else {
+        return Err(ScumboError::NothingPlaying);
+    };
+    let title = data.title();
+
+    let listening = current_listeners();
+    if !listening.contains(&ctx.author().id) {
+        return Err(ScumboError::Permission(
+            "You have to be listening to vote.".into(),
+        ));
+    }
+
+    let required = required_votes(listening.len(), percent);
+    let Some(ballot) = q
+        .vote_skip(&track, ctx.author().id, &listening, required)
+        .await
+    else {
+        return Err(ScumboError::TrackChanged);
+    };
+    let (votes, message) = match ballot {
+        // The message of the vote is closed by the command which started it.
+        Ballot::Passed => {
+            ctx.send(super::utils::reply(
+                "Info",
+                format!("The vote passed, skipped {title}."),
+            ))
+            .await?;
+            return Ok(());
+        }
+        Ballot::Counted { votes, message } => (votes, message),
+    };
+
+    // Someone else already started the vote, whose message shows the count.
+    if let Some((channel, message)) = message {
+        drop(
+            channel
+                .edit_message(
+                    ctx,
+                    message,
+                    serenity::all::EditMessage::new().embed(embed(&title, votes, required)),
+                )
+                .await,
+        );
+        ctx.send(super::utils::reply(
+            "Info",
+            format!("Voted to skip {title}, {votes}/{required} votes."),
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let button_id = format!("{}vote-skip", ctx.id());
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed(&title, votes, required))
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(&button_id).label("Vote to skip"),
+                ])]),
+        )
+        .await?;
+    let sent = handle.message().await?;
+    q.set_vote_message(&track, (sent.channel_id, sent.id));
+
+    let track_changed = || q.current().map(|handle| handle.uuid()) != Some(track.uuid());
+    // Wakes up once the track changed, e.g. because it ended or another vote passed.
+    let changed = || async {
+        while !track_changed() {
+            tokio::time::sleep(TRACK_CHECK_INTERVAL).await;
+        }
+    };
+    let mut outcome = "The vote timed out.";
+    loop {
+        let collector = ComponentInteractionCollector::new(ctx)
+            .filter({
+                let button_id = button_id.clone();
+                move |press| press.data.custom_id == button_id
+            })
+            .timeout(VOTE_TIMEOUT);
+        let press = tokio::select! {
+            press = collector => press,
+            () = changed() => None,
+        };
+        let Some(press) = press else {
+            break;
+        };
+
+        let listening = current_listeners();
+        if !listening.contains(&press.user.id) {
+            press
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content("You have to be listening to vote.")
+                            .ephemeral(true),
+                    ),
+                )
+                .await?;
+            continue;
+        }
+
+        let required = required_votes(listening.len(), percent);
+        let Some(ballot) = q
+            .vote_skip(&track, press.user.id, &listening, required)
+            .await
+        else {
+            // The message is closed below.
+            press
+                .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                .await?;
+            break;
+        };
+
+        let embed = match ballot {
+            Ballot::Passed => ended(&title, "The vote passed, the track was skipped."),
+            Ballot::Counted { votes, .. } => embed(&title, votes, required),
+        };
+        let mut response = CreateInteractionResponseMessage::new().embed(embed);
+        if ballot == Ballot::Passed {
+            response = response.components(vec![]);
+        }
+        press
+            .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+            .await?;
+        if ballot == Ballot::Passed {
+            return Ok(());
+        }
+    }
+
+    if track_changed() {
+        outcome = "The track changed, the vote is over.";
+    }
+    handle
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(ended(&title, outcome))
+                .components(vec![]),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required() {
+        assert_eq!(required_votes(0, 50), 1);
+        assert_eq!(required_votes(1, 50), 1);
+        assert_eq!(required_votes(3, 50), 2);
+        assert_eq!(required_votes(4, 50), 2);
+        assert_eq!(required_votes(4, 100), 4);
+        assert_eq!(required_votes(10, 1), 1);
+    }
+}